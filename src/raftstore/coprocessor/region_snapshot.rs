@@ -15,7 +15,7 @@ use std::u64;
 use std::sync::Arc;
 use rocksdb::{DB, Range, SeekKey, DBVector, DBIterator};
 use kvproto::metapb::Region;
-use util::properties::{UserProperties, GetPropertiesOptions};
+use util::properties::{MvccProperties, GetPropertiesOptions};
 
 use raftstore::store::engine::{SyncSnapshot, Snapshot, Peekable, Iterable, IterOption};
 use raftstore::store::{keys, util, PeerStorage};
@@ -112,7 +112,7 @@ impl RegionSnapshot {
     pub fn get_properties_cf(&self,
                              cf: &str,
                              opts: &GetPropertiesOptions)
-                             -> Result<UserProperties> {
+                             -> Result<MvccProperties> {
         let db = self.snap.get_db();
         let cf = try!(db.cf_handle(cf)
             .ok_or_else(|| Error::RocksDb(format!("cf {} not found.", cf))));
@@ -128,11 +128,11 @@ impl RegionSnapshot {
         let max_ts = opts.max_ts.unwrap_or(u64::MAX);
 
         // Aggregates properties from multiple tables.
-        let mut res = UserProperties::new();
+        let mut res = MvccProperties::new();
         for (_, v) in &*collection {
             let props = v.user_collected_properties();
-            let other = try!(UserProperties::decode(props));
-            if other.min_ts > max_ts {
+            let other = try!(MvccProperties::decode(props));
+            if !other.overlaps_ts(max_ts) {
                 continue;
             }
             res.add(&other);
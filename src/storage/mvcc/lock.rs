@@ -102,7 +102,11 @@ impl Lock {
             return Err(Error::BadFormatLock);
         }
         let lock_type = try!(LockType::from_u8(try!(b.read_u8())).ok_or(Error::BadFormatLock));
-        let primary = try!(b.decode_compact_bytes());
+        let primary = match b.decode_compact_bytes() {
+            Ok(primary) => primary,
+            // The length of the primary is corrupted.
+            Err(_) => return Err(Error::BadFormatLock),
+        };
         let ts = try!(b.decode_var_u64());
         let ttl = if b.is_empty() {
             0
@@ -115,16 +119,13 @@ impl Lock {
         }
 
         let flag = try!(b.read_u8());
-        assert_eq!(flag,
-                   SHORT_VALUE_PREFIX,
-                   "invalid flag [{:?}] in write",
-                   flag);
+        if flag != SHORT_VALUE_PREFIX {
+            return Err(Error::BadFormatLock);
+        }
 
         let len = try!(b.read_u8());
         if len as usize != b.len() {
-            panic!("short value len [{}] not equal to content len [{}]",
-                   len,
-                   b.len());
+            return Err(Error::BadFormatLock);
         }
 
         Ok(Lock::new(lock_type, primary, ts, ttl, Some(b.to_vec())))
@@ -196,5 +197,20 @@ mod tests {
                              Some(b"short_value".to_vec()));
         let v = lock.to_bytes();
         assert!(Lock::parse(&v[..4]).is_err());
+        // A truncated short value.
+        assert!(Lock::parse(&v[..v.len() - 1]).is_err());
+
+        // An invalid short value flag.
+        let mut v = Lock::new(LockType::Put, b"pk".to_vec(), 1, 10, None).to_bytes();
+        v.extend_from_slice(b"x\0");
+        assert!(Lock::parse(&v[..]).is_err());
+
+        // A negative and a huge length of the primary, e.g. of a write parsed as a lock.
+        for v in &[&b"P\x01"[..], &b"P\xfe\xff\xff\xff\xff\xff\xff\xff\x7fpk"[..]] {
+            match Lock::parse(v) {
+                Err(Error::BadFormatLock) => {}
+                res => panic!("expect BadFormatLock, got {:?}", res),
+            }
+        }
     }
 }
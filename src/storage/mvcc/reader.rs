@@ -507,7 +507,7 @@ impl<'a> MvccReader<'a> {
             return true;
         }
         // A lot of non-effective MVCC versions to GC.
-        if props.num_versions as f64 > props.num_puts as f64 * ratio_threshold {
+        if props.puts_valid && props.num_versions as f64 > props.num_puts as f64 * ratio_threshold {
             return true;
         }
 
@@ -533,7 +533,7 @@ mod tests {
     use raftstore::errors::Result;
     use raftstore::store::keys;
     use util::rocksdb::{self as rocksdb_util, CFOptions};
-    use util::properties::{UserProperties, UserPropertiesCollectorFactory};
+    use util::properties::{MvccProperties, UserPropertiesCollectorFactory};
 
     struct RegionEngine {
         db: Arc<DB>,
@@ -653,7 +653,7 @@ mod tests {
         region
     }
 
-    fn get_properties(db: Arc<DB>, region: Region, safe_point: u64) -> Result<UserProperties> {
+    fn get_properties(db: Arc<DB>, region: Region, safe_point: u64) -> Result<MvccProperties> {
         let mut opts = GetPropertiesOptions::default();
         opts.max_ts = Some(safe_point);
         let snap = RegionSnapshot::from_raw(db.clone(), region.clone());
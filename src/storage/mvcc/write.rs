@@ -99,16 +99,13 @@ impl Write {
         }
 
         let flag = try!(b.read_u8());
-        assert_eq!(flag,
-                   SHORT_VALUE_PREFIX,
-                   "invalid flag [{:?}] in write",
-                   flag);
+        if flag != SHORT_VALUE_PREFIX {
+            return Err(Error::BadFormatWrite);
+        }
 
         let len = try!(b.read_u8());
         if len as usize != b.len() {
-            panic!("short value len [{}] not equal to content len [{}]",
-                   len,
-                   b.len());
+            return Err(Error::BadFormatWrite);
         }
         Ok(Write::new(write_type, start_ts, Some(b.to_vec())))
     }
@@ -172,5 +169,12 @@ mod tests {
         let lock = Write::new(WriteType::Lock, 1, Some(b"short_value".to_vec()));
         let v = lock.to_bytes();
         assert!(Write::parse(&v[..1]).is_err());
+        // A truncated short value.
+        assert!(Write::parse(&v[..v.len() - 1]).is_err());
+
+        // An invalid short value flag.
+        let mut v = Write::new(WriteType::Put, 1, None).to_bytes();
+        v.extend_from_slice(b"x\0");
+        assert!(Write::parse(&v[..]).is_err());
     }
 }
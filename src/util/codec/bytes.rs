@@ -11,6 +11,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::cmp;
 use std::io::{Read, Write};
 
 use super::{Result, Error};
@@ -19,6 +20,9 @@ use util::codec::number::{NumberEncoder, NumberDecoder};
 const ENC_GROUP_SIZE: usize = 8;
 const ENC_MARKER: u8 = b'\xff';
 const ENC_PADDING: [u8; ENC_GROUP_SIZE] = [0; ENC_GROUP_SIZE];
+// The maximal capacity reserved before reading compact bytes, larger ones grow
+// as they are read.
+const MAX_COMPACT_BYTES_PREALLOC: usize = 1024 * 1024;
 
 // returns the maximum encoded bytes size.
 pub fn max_encoded_bytes_size(n: usize) -> usize {
@@ -109,9 +113,18 @@ pub fn encoded_compact_len(mut encoded: &[u8]) -> usize {
 pub trait CompactBytesDecoder: NumberDecoder {
     /// `decode_compact_bytes` decodes bytes which is encoded by `encode_compact_bytes` before.
     fn decode_compact_bytes(&mut self) -> Result<Vec<u8>> {
-        let vn = try!(self.decode_var_i64()) as usize;
-        let mut data = vec![0; vn];
-        try!(self.read_exact(&mut data));
+        let vn = try!(self.decode_var_i64());
+        if vn < 0 {
+            return Err(Error::KeyLength);
+        }
+        // Don't trust the length before reading the data, only a bounded buffer
+        // is reserved up front, so a corrupted length can't exhaust memory.
+        let cap = cmp::min(vn as u64, MAX_COMPACT_BYTES_PREALLOC as u64) as usize;
+        let mut data = Vec::with_capacity(cap);
+        try!(Read::take(&mut *self, vn as u64).read_to_end(&mut data));
+        if data.len() as u64 != vn as u64 {
+            return Err(Error::KeyLength);
+        }
         Ok(data)
     }
 }
@@ -191,7 +204,9 @@ impl<'a> BytesDecoder for &'a [u8] {
 mod tests {
     use super::*;
     use util::codec::{number, bytes};
+    use util::codec::number::NumberEncoder;
     use std::cmp::Ordering;
+    use std::i64;
 
     #[test]
     fn test_enc_dec_bytes() {
@@ -314,6 +329,24 @@ mod tests {
             assert!(input.is_empty());
             assert_eq!(decoded, s.as_bytes());
         }
+
+        // Bytes larger than the capacity reserved up front.
+        let data = vec![b'x'; MAX_COMPACT_BYTES_PREALLOC + 1];
+        let mut buf = vec![];
+        buf.encode_compact_bytes(&data).unwrap();
+        assert_eq!(buf.as_slice().decode_compact_bytes().unwrap(), data);
+
+        // A negative length.
+        let mut buf = vec![];
+        buf.encode_var_i64(-1).unwrap();
+        assert!(buf.as_slice().decode_compact_bytes().is_err());
+        // A length larger than the data, including a huge one.
+        for &n in &[6, i64::MAX] {
+            let mut buf = vec![];
+            buf.encode_var_i64(n).unwrap();
+            buf.extend_from_slice(b"hello");
+            assert!(buf.as_slice().decode_compact_bytes().is_err());
+        }
     }
 
     use test::Bencher;
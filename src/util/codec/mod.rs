@@ -38,6 +38,11 @@ quick_error! {
         KeyLength {description("bad format key(length)")}
         KeyPadding {description("bad format key(padding)")}
         KeyNotFound {description("key not found")}
+        ChecksumMismatch {description("checksum mismatch")}
+        UnsupportedPropVersion(version: u32) {
+            description("unsupported properties version")
+            display("unsupported properties version {}", version)
+        }
         InvalidDataType(reason: String) {
             description("invalid data type")
             display("{}", reason)
@@ -62,6 +67,8 @@ impl Error {
             Error::KeyLength => Some(Error::KeyLength),
             Error::KeyPadding => Some(Error::KeyPadding),
             Error::KeyNotFound => Some(Error::KeyNotFound),
+            Error::ChecksumMismatch => Some(Error::ChecksumMismatch),
+            Error::UnsupportedPropVersion(v) => Some(Error::UnsupportedPropVersion(v)),
             Error::InvalidDataType(ref r) => Some(Error::InvalidDataType(r.clone())),
             Error::Encoding(e) => Some(Error::Encoding(e)),
             Error::Protobuf(_) |
@@ -425,6 +425,17 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_var_u64_len() {
+        let cases = [(0, 1), (127, 1), (128, 2), (u64::MAX, MAX_VAR_U64_LEN)];
+        for &(v, len) in &cases {
+            let mut buf = vec![];
+            buf.encode_var_u64(v).unwrap();
+            assert_eq!(buf.len(), len, "{}", v);
+            assert_eq!(buf.as_slice().decode_var_u64().unwrap(), v);
+        }
+    }
+
     // test if a `Result` is expected io error.
     macro_rules! check_error {
         ($e:expr, $k:expr) => {
@@ -12,228 +12,5832 @@
 // limitations under the License.
 
 use std::cmp;
-use std::collections::HashMap;
-use std::u64;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::hash::Hasher;
+use std::iter::FromIterator;
+use std::mem;
+use std::ops::{BitOr, Range};
+use std::{i64, u32, u64};
+use std::sync::Arc;
+use std::time::Instant;
 
-use storage::mvcc::{Write, WriteType};
+use storage::mvcc::{Lock, Write, WriteType};
 use storage::types;
 use raftstore::store::keys;
+use fnv::FnvHasher;
+use futures::{stream, BoxFuture, Future, Stream};
+use serde_json::{Map, Value};
+use crc::crc32::{self, Digest, Hasher32};
 use rocksdb::{DBEntryType, UserCollectedProperties, TablePropertiesCollector,
               TablePropertiesCollectorFactory};
+use util::{duration_to_nanos, escape};
 use util::codec;
-use util::codec::number::{NumberEncoder, NumberDecoder};
+use util::codec::number::{self, NumberEncoder, NumberDecoder};
+use util::codec::bytes::{BytesEncoder, BytesDecoder, CompactBytesDecoder};
 
 #[derive(Clone, Debug, Default)]
 pub struct GetPropertiesOptions {
     pub max_ts: Option<u64>,
 }
 
-const PROP_MIN_TS: &'static str = "tikv.min_ts";
-const PROP_MAX_TS: &'static str = "tikv.max_ts";
-const PROP_NUM_ROWS: &'static str = "tikv.num_rows";
-const PROP_NUM_PUTS: &'static str = "tikv.num_puts";
-const PROP_NUM_VERSIONS: &'static str = "tikv.num_versions";
-const PROP_MAX_ROW_VERSIONS: &'static str = "tikv.max_row_versions";
+/// `PropertiesFlags` controls which properties a collector computes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PropertiesFlags {
+    bits: u32,
+}
+
+/// Collects all MVCC properties.
+pub const MVCC_PROPERTIES: PropertiesFlags = PropertiesFlags { bits: 0b01 };
+/// Collects only `num_versions` and `num_deletes`, enough for `delete_ratio`.
+pub const DELETE_RATIO: PropertiesFlags = PropertiesFlags { bits: 0b10 };
+/// Counts entries of unexpected types into `num_errors`.
+pub const STRICT_ENTRY_TYPES: PropertiesFlags = PropertiesFlags { bits: 0b100 };
+/// Collects a HyperLogLog sketch of row keys, see `estimated_distinct_rows`.
+pub const KEY_CARDINALITY: PropertiesFlags = PropertiesFlags { bits: 0b1000 };
+/// Skips parsing writes, so `num_puts`, `num_deletes`, `num_rollbacks`,
+/// `num_fully_deleted_rows` and `num_pure_tombstone_rows` are not collected,
+/// see `MvccProperties::puts_valid`.
+pub const FAST_COUNTS_ONLY: PropertiesFlags = PropertiesFlags { bits: 0b10000 };
+/// Collects a bloom filter of user key prefixes, see `may_contain_prefix`.
+pub const PREFIX_BLOOM: PropertiesFlags = PropertiesFlags { bits: 0b100000 };
+/// Measures the time spent on collecting, see `UserProperties::collect_nanos`.
+pub const TIMING: PropertiesFlags = PropertiesFlags { bits: 0b1000000 };
+/// Records the first valid data key added, see `UserProperties::first_key`.
+pub const FIRST_KEY: PropertiesFlags = PropertiesFlags { bits: 0b10000000 };
+
+impl PropertiesFlags {
+    pub fn empty() -> PropertiesFlags {
+        PropertiesFlags { bits: 0 }
+    }
+
+    // Unknown bits are kept, they may be set by a newer version.
+    pub fn from_bits(bits: u32) -> PropertiesFlags {
+        PropertiesFlags { bits: bits }
+    }
+
+    pub fn bits(&self) -> u32 {
+        self.bits
+    }
+
+    pub fn contains(&self, other: PropertiesFlags) -> bool {
+        self.bits & other.bits == other.bits
+    }
+
+    pub fn intersects(&self, other: PropertiesFlags) -> bool {
+        self.bits & other.bits != 0
+    }
+}
+
+impl BitOr for PropertiesFlags {
+    type Output = PropertiesFlags;
+
+    fn bitor(self, other: PropertiesFlags) -> PropertiesFlags {
+        PropertiesFlags { bits: self.bits | other.bits }
+    }
+}
+
+// The custom properties of `PropertyRegistry` are in this namespace, so that
+// they never collide with the built-in ones or keys of other collectors.
+const CUSTOM_PROP_NAMESPACE: &'static str = "tikv.custom.";
+// The schema version of the encoded properties, bump it when the encoding changes.
+const PROP_VERSION: &'static str = "tikv.prop_version";
+const PROP_CRC: &'static str = "tikv.prop_crc";
+const PROP_VERSION_V1: u32 = 1;
+// Integers of MVCC properties are encoded in the varint form since version 2.
+const PROP_VERSION_V2: u32 = 2;
+// MVCC properties are checksummed by `PROP_CRC` since version 3.
+const PROP_VERSION_V3: u32 = 3;
+const CURRENT_PROP_VERSION: u32 = PROP_VERSION_V3;
+// The version of the layout of `MvccProperties::to_wire`, bump it only when
+// the layout changes incompatibly, appending fields is compatible.
+const WIRE_VERSION: u32 = 1;
+
 const PROP_NUM_ERRORS: &'static str = "tikv.num_errors";
+const PROP_NUM_NON_DATA_KEYS: &'static str = "tikv.num_non_data_keys";
+const PROP_FATAL_ERRORS: &'static str = "tikv.fatal_errors";
+const PROP_SUSPECT: &'static str = "tikv.suspect";
+const PROP_CF_NAME: &'static str = "tikv.cf_name";
+const PROP_COLLECTED_FLAGS: &'static str = "tikv.collected_flags";
+const PROP_CF_ID: &'static str = "tikv.cf_id";
+const PROP_REGION_ID: &'static str = "tikv.region_id";
+const PROP_ENTRY_COUNT: &'static str = "tikv.entry_count";
+const PROP_COLLECT_NANOS: &'static str = "tikv.collect_nanos";
+const PROP_FIRST_DATA_KEY: &'static str = "tikv.first_data_key";
+const PROP_DISTINCT_PREFIXES: &'static str = "tikv.distinct_prefixes";
+const PROP_SMALLEST_KEY: &'static str = "tikv.smallest_key";
+const PROP_LARGEST_KEY: &'static str = "tikv.largest_key";
+const PROP_KEY_HLL: &'static str = "tikv.key_hll";
+const PROP_VERSIONS_HISTOGRAM: &'static str = "tikv.versions_histogram";
+const PROP_KEY_PREFIX_BLOOM: &'static str = "tikv.key_prefix_bloom";
+const PROP_KEY_PREFIX_LEN: &'static str = "tikv.key_prefix_len";
+const PROP_NUM_LOCKS: &'static str = "tikv.num_locks";
+const PROP_MIN_LOCK_TS: &'static str = "tikv.min_lock_ts";
+const PROP_MAX_LOCK_TS: &'static str = "tikv.max_lock_ts";
+const PROP_TOTAL_DEFAULT_SIZE: &'static str = "tikv.total_default_size";
+const PROP_RAW_NUM_KEYS: &'static str = "tikv.raw_num_keys";
+const PROP_RAW_TOTAL_BYTES: &'static str = "tikv.raw_total_bytes";
+const PROP_TOTAL_KEY_SIZE: &'static str = "tikv.total_key_size";
+const PROP_TOTAL_VALUE_SIZE: &'static str = "tikv.total_value_size";
+
+/// `PropKey` is the key of an integer property of `MvccProperties`, the
+/// integer properties are encoded and decoded by iterating `PROP_KEYS`.
+///
+/// New variants may only be appended, see `PROP_KEYS`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PropKey {
+    MinTs,
+    MaxTs,
+    NumRows,
+    NumPuts,
+    PutsValid,
+    NumDeletes,
+    NumRollbacks,
+    NumMerges,
+    NumVersions,
+    MaxRowVersions,
+    NumHotRows,
+    NumTsAnomalies,
+    NumFullyDeletedRows,
+    NumPureTombstoneRows,
+    VersionsBelowSafepoint,
+    NumPutsBelowSafepoint,
+    FirstRowVersions,
+    LastRowVersions,
+    MinRowLatestTs,
+    MaxValueSize,
+    EntryTypePuts,
+    EntryTypeDeletes,
+    EntryTypeOther,
+    NumDuplicateVersions,
+    MaxVersionGap,
+    MinStartTs,
+    MaxStartTs,
+    NumInlinedValues,
+    NumPointerValues,
+    HasDeletes,
+    NumNonDataVersions,
+    TotalRowKeyLen,
+    NumSingleVersionRows,
+}
+
+// `to_wire` encodes the integer properties by their positions in `PROP_KEYS`,
+// so keys may only be appended, reordering or inserting them changes the wire
+// format silently.
+const PROP_KEYS: &'static [PropKey] = &[PropKey::MinTs,
+                                       PropKey::MaxTs,
+                                       PropKey::NumRows,
+                                       PropKey::NumPuts,
+                                       PropKey::PutsValid,
+                                       PropKey::NumDeletes,
+                                       PropKey::NumRollbacks,
+                                       PropKey::NumMerges,
+                                       PropKey::NumVersions,
+                                       PropKey::MaxRowVersions,
+                                       PropKey::NumHotRows,
+                                       PropKey::NumTsAnomalies,
+                                       PropKey::NumFullyDeletedRows,
+                                       PropKey::NumPureTombstoneRows,
+                                       PropKey::VersionsBelowSafepoint,
+                                       PropKey::NumPutsBelowSafepoint,
+                                       PropKey::FirstRowVersions,
+                                       PropKey::LastRowVersions,
+                                       PropKey::MinRowLatestTs,
+                                       PropKey::MaxValueSize,
+                                       PropKey::EntryTypePuts,
+                                       PropKey::EntryTypeDeletes,
+                                       PropKey::EntryTypeOther,
+                                       PropKey::NumDuplicateVersions,
+                                       PropKey::MaxVersionGap,
+                                       PropKey::MinStartTs,
+                                       PropKey::MaxStartTs,
+                                       PropKey::NumInlinedValues,
+                                       PropKey::NumPointerValues,
+                                       PropKey::HasDeletes,
+                                       PropKey::NumNonDataVersions,
+                                       PropKey::TotalRowKeyLen,
+                                       PropKey::NumSingleVersionRows];
+
+impl PropKey {
+    fn name(&self) -> &'static str {
+        match *self {
+            PropKey::MinTs => "tikv.min_ts",
+            PropKey::MaxTs => "tikv.max_ts",
+            PropKey::NumRows => "tikv.num_rows",
+            PropKey::NumPuts => "tikv.num_puts",
+            PropKey::PutsValid => "tikv.puts_valid",
+            PropKey::NumDeletes => "tikv.num_deletes",
+            PropKey::NumRollbacks => "tikv.num_rollbacks",
+            PropKey::NumMerges => "tikv.num_merges",
+            PropKey::NumVersions => "tikv.num_versions",
+            PropKey::MaxRowVersions => "tikv.max_row_versions",
+            PropKey::NumHotRows => "tikv.num_hot_rows",
+            PropKey::NumTsAnomalies => "tikv.num_ts_anomalies",
+            PropKey::NumFullyDeletedRows => "tikv.num_fully_deleted_rows",
+            PropKey::NumPureTombstoneRows => "tikv.num_pure_tombstone_rows",
+            PropKey::VersionsBelowSafepoint => "tikv.versions_below_safepoint",
+            PropKey::NumPutsBelowSafepoint => "tikv.num_puts_below_safepoint",
+            PropKey::FirstRowVersions => "tikv.first_row_versions",
+            PropKey::LastRowVersions => "tikv.last_row_versions",
+            PropKey::MinRowLatestTs => "tikv.min_row_latest_ts",
+            PropKey::MaxValueSize => "tikv.max_value_size",
+            PropKey::EntryTypePuts => "tikv.entry_type_puts",
+            PropKey::EntryTypeDeletes => "tikv.entry_type_deletes",
+            PropKey::EntryTypeOther => "tikv.entry_type_other",
+            PropKey::NumDuplicateVersions => "tikv.num_duplicate_versions",
+            PropKey::MaxVersionGap => "tikv.max_version_gap",
+            PropKey::MinStartTs => "tikv.min_start_ts",
+            PropKey::MaxStartTs => "tikv.max_start_ts",
+            PropKey::NumInlinedValues => "tikv.num_inlined_values",
+            PropKey::NumPointerValues => "tikv.num_pointer_values",
+            PropKey::HasDeletes => "tikv.has_deletes",
+            PropKey::NumNonDataVersions => "tikv.num_non_data_versions",
+            PropKey::TotalRowKeyLen => "tikv.total_row_key_len",
+            PropKey::NumSingleVersionRows => "tikv.num_single_version_rows",
+        }
+    }
+
+    pub fn as_bytes(&self) -> &'static [u8] {
+        self.name().as_bytes()
+    }
+
+    pub fn from_bytes(key: &[u8]) -> Option<PropKey> {
+        PROP_KEYS.iter().find(|k| k.as_bytes() == key).cloned()
+    }
+
+    // Properties added later may be absent in SSTs written by older versions,
+    // they are decoded as the default value. Others are required.
+    fn default_value(&self) -> Option<u64> {
+        match *self {
+            PropKey::MinTs |
+            PropKey::MaxTs |
+            PropKey::NumRows |
+            PropKey::NumPuts |
+            PropKey::NumVersions |
+            PropKey::MaxRowVersions => None,
+            PropKey::PutsValid => Some(1),
+            PropKey::MinRowLatestTs | PropKey::MinStartTs => Some(u64::MAX),
+            _ => Some(0),
+        }
+    }
+
+    fn decode<T: DecodeU64>(&self, props: &T) -> Result<u64, codec::Error> {
+        match self.default_value() {
+            Some(default) => props.decode_u64_or(self.name(), default),
+            None => props.decode_u64(self.name()),
+        }
+    }
+}
 
 #[derive(Clone, Debug, Default)]
-pub struct UserProperties {
+pub struct MvccProperties {
     pub min_ts: u64, // The minimal timestamp.
     pub max_ts: u64, // The maximal timestamp.
     pub num_rows: u64, // The number of rows.
     pub num_puts: u64, // The number of MVCC puts of all rows.
-    pub num_versions: u64, // The number of MVCC versions of all rows.
+    pub puts_valid: bool, // Whether the counters computed by parsing writes are collected.
+    pub num_deletes: u64, // The number of MVCC deletes of all rows.
+    pub num_rollbacks: u64, // The number of rollback records of all rows.
+    pub num_merges: u64, // The number of merge entries, which are not MVCC versions.
+    // The number of write records of all rows, including lock and rollback ones,
+    // which `need_gc` relies on to find regions of only such garbage, see
+    // `num_data_versions` for the versions of the data.
+    pub num_versions: u64,
     pub max_row_versions: u64, // The maximal number of MVCC versions of a single row.
-    pub num_errors: u64,
+    pub num_hot_rows: u64, // The number of rows with more versions than a threshold.
+    pub num_ts_anomalies: u64, // The number of versions newer than the previous one of the row.
+    // The number of versions with the same ts as the previous one of the row,
+    // which should never happen unless the keys are corrupted.
+    pub num_duplicate_versions: u64,
+    pub max_version_gap: u64, // The maximal ts gap between consecutive versions of a row.
+    // The range of the start ts of parsed writes, while `min_ts` and `max_ts`
+    // are of the commit ts. Only sampled writes are counted with `sample_rate`.
+    pub min_start_ts: u64,
+    pub max_start_ts: u64,
+    // The number of puts with the value inlined as a short value, and the
+    // number of puts whose value is in the default column family.
+    pub num_inlined_values: u64,
+    pub num_pointer_values: u64,
+    // Whether any delete write is seen. Unlike `num_deletes`, it's collected
+    // even if writes are not parsed, since only the write type is checked.
+    pub deletes_seen: bool,
+    // The number of versions which are lock or rollback records rather than
+    // versions of the data, they are still counted in `num_versions`.
+    pub num_non_data_versions: u64,
+    pub total_row_key_len: u64, // The total length of user keys of all rows.
+    pub num_single_version_rows: u64, // The number of rows with only one version.
+    pub num_fully_deleted_rows: u64, // The number of rows whose latest write is a delete.
+    pub num_pure_tombstone_rows: u64, // The number of rows with deletes but no puts.
+    pub versions_below_safepoint: u64, // The number of MVCC versions older than the safe point.
+    // The number of puts older than the safe point and shadowed by a newer
+    // version of the row, which can be removed by GC.
+    pub num_puts_below_safepoint: u64,
+    pub first_row_versions: u64, // The number of MVCC versions of the first row.
+    pub last_row_versions: u64, // The number of MVCC versions of the last row.
+    // The minimal latest ts of all rows, which is the latest ts of the row
+    // updated the longest ago, u64::MAX if unknown.
+    pub min_row_latest_ts: u64,
+    // The size of the largest value put, whatever the column family is.
+    pub max_value_size: u64,
+    // The number of entries by `DBEntryType`, including invalid ones.
+    pub entry_type_puts: u64,
+    pub entry_type_deletes: u64,
+    pub entry_type_other: u64,
+    // The flags of the collector, `None` if the properties predate them.
+    // Aggregated properties only have the flags all operands have.
+    pub collected_flags: Option<PropertiesFlags>,
+    // The number of rows by versions, see `versions_bucket` for the buckets.
+    pub versions_histogram: [u64; VERSIONS_HISTOGRAM_BUCKETS],
+    pub smallest_key: Vec<u8>, // The smallest row key (without ts), empty if unknown.
+    pub largest_key: Vec<u8>, // The largest row key (without ts), empty if unknown.
+    pub key_hll: Vec<u8>, // The HyperLogLog registers of row keys, empty if not collected.
+    pub prefix_bloom: Vec<u8>, // The bloom filter of user key prefixes, empty if not collected.
+    pub bloom_prefix_len: u64, // The length of user key prefixes in `prefix_bloom`.
 }
 
-impl UserProperties {
-    pub fn new() -> UserProperties {
-        UserProperties {
+impl MvccProperties {
+    pub fn new() -> MvccProperties {
+        MvccProperties {
             min_ts: u64::MAX,
             max_ts: u64::MIN,
             num_rows: 0,
             num_puts: 0,
+            puts_valid: true,
+            num_deletes: 0,
+            num_rollbacks: 0,
+            num_merges: 0,
             num_versions: 0,
             max_row_versions: 0,
-            num_errors: 0,
+            num_hot_rows: 0,
+            num_ts_anomalies: 0,
+            num_duplicate_versions: 0,
+            max_version_gap: 0,
+            min_start_ts: u64::MAX,
+            max_start_ts: u64::MIN,
+            num_inlined_values: 0,
+            num_pointer_values: 0,
+            deletes_seen: false,
+            num_non_data_versions: 0,
+            total_row_key_len: 0,
+            num_single_version_rows: 0,
+            num_fully_deleted_rows: 0,
+            num_pure_tombstone_rows: 0,
+            versions_below_safepoint: 0,
+            num_puts_below_safepoint: 0,
+            first_row_versions: 0,
+            last_row_versions: 0,
+            min_row_latest_ts: u64::MAX,
+            max_value_size: 0,
+            entry_type_puts: 0,
+            entry_type_deletes: 0,
+            entry_type_other: 0,
+            collected_flags: None,
+            versions_histogram: [0; VERSIONS_HISTOGRAM_BUCKETS],
+            smallest_key: Vec::new(),
+            largest_key: Vec::new(),
+            key_hll: Vec::new(),
+            prefix_bloom: Vec::new(),
+            bloom_prefix_len: 0,
+        }
+    }
+
+    /// Builds properties with the given baseline fields, and the others
+    /// as in `new`. It is handy to fabricate properties in tests and tools.
+    pub fn with(min_ts: u64,
+                max_ts: u64,
+                num_rows: u64,
+                num_puts: u64,
+                num_versions: u64,
+                max_row_versions: u64)
+                -> MvccProperties {
+        let mut props = MvccProperties::new();
+        props.min_ts = min_ts;
+        props.max_ts = max_ts;
+        props.num_rows = num_rows;
+        props.num_puts = num_puts;
+        props.num_versions = num_versions;
+        props.max_row_versions = max_row_versions;
+        props
+    }
+
+    /// Returns true if no MVCC versions were collected.
+    pub fn is_empty(&self) -> bool {
+        self.num_versions == 0
+    }
+
+    /// Checks the invariants between the fields, which are broken if the
+    /// properties are corrupted or miscomputed.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.num_puts > self.num_versions {
+            return Err(format!("num_puts {} > num_versions {}", self.num_puts, self.num_versions));
+        }
+        if self.num_rows > self.num_versions {
+            return Err(format!("num_rows {} > num_versions {}", self.num_rows, self.num_versions));
+        }
+        if self.max_row_versions > self.num_versions {
+            return Err(format!("max_row_versions {} > num_versions {}",
+                               self.max_row_versions,
+                               self.num_versions));
+        }
+        // `min_ts` is still `u64::MAX` if there are no versions or it's not collected.
+        if !self.is_empty() && self.is_field_valid(PropKey::MinTs) && self.min_ts > self.max_ts {
+            return Err(format!("min_ts {} > max_ts {}", self.min_ts, self.max_ts));
+        }
+        Ok(())
+    }
+
+    /// Returns true if there is any delete write. It's cheaper to collect than
+    /// `num_deletes`, so it's known even if `puts_valid` is false.
+    pub fn has_deletes(&self) -> bool {
+        // Properties written before `deletes_seen` may still have `num_deletes`.
+        self.deletes_seen || self.num_deletes > 0
+    }
+
+    /// Returns false if `field` is not collected, or not collected by all the
+    /// aggregated properties, in which case its value is underestimated.
+    /// Properties without `collected_flags` are assumed to collect all fields.
+    pub fn is_field_valid(&self, field: PropKey) -> bool {
+        let full = self.collected_flags.map_or(true, |f| f.contains(MVCC_PROPERTIES));
+        match field {
+            PropKey::PutsValid |
+            PropKey::HasDeletes |
+            PropKey::NumMerges |
+            PropKey::NumVersions |
+            PropKey::VersionsBelowSafepoint |
+            PropKey::MaxValueSize |
+            PropKey::EntryTypePuts |
+            PropKey::EntryTypeDeletes |
+            PropKey::EntryTypeOther => true,
+            PropKey::NumPuts |
+            PropKey::NumDeletes |
+            PropKey::NumRollbacks |
+            PropKey::MinStartTs |
+            PropKey::MaxStartTs |
+            PropKey::NumInlinedValues |
+            PropKey::NumPointerValues |
+            PropKey::NumNonDataVersions => self.puts_valid,
+            PropKey::NumFullyDeletedRows |
+            PropKey::NumPureTombstoneRows |
+            PropKey::NumPutsBelowSafepoint => full && self.puts_valid,
+            _ => full,
         }
     }
 
-    pub fn add(&mut self, other: &UserProperties) {
+    pub fn add(&mut self, other: &MvccProperties) {
+        // An empty operand has no meaningful timestamps, and `min_ts` may
+        // still be `u64::MAX`, so skip it to not pollute the aggregation.
+        // Note that `encode` still writes empty properties, otherwise readers
+        // can't tell an empty SST from an SST without properties.
+        // Values and entry types are collected in all column families, even
+        // without versions.
+        self.max_value_size = cmp::max(self.max_value_size, other.max_value_size);
+        self.entry_type_puts = self.entry_type_puts.saturating_add(other.entry_type_puts);
+        self.entry_type_deletes = self.entry_type_deletes.saturating_add(other.entry_type_deletes);
+        self.entry_type_other = self.entry_type_other.saturating_add(other.entry_type_other);
+        if other.is_empty() {
+            return;
+        }
+        // Sketches only cover all rows if every operand has one.
+        if self.is_empty() {
+            self.collected_flags = other.collected_flags;
+            self.key_hll = other.key_hll.clone();
+            self.prefix_bloom = other.prefix_bloom.clone();
+            self.bloom_prefix_len = other.bloom_prefix_len;
+        } else {
+            self.collected_flags = match (self.collected_flags, other.collected_flags) {
+                (Some(a), Some(b)) => Some(merge_flags(a, b)),
+                (a, b) => a.or(b),
+            };
+            if self.key_hll.len() == other.key_hll.len() {
+                hll_merge(&mut self.key_hll, &other.key_hll);
+            } else {
+                self.key_hll.clear();
+            }
+            if self.prefix_bloom.len() == other.prefix_bloom.len() &&
+               self.bloom_prefix_len == other.bloom_prefix_len {
+                bloom_merge(&mut self.prefix_bloom, &other.prefix_bloom);
+            } else {
+                self.prefix_bloom.clear();
+            }
+        }
         self.min_ts = cmp::min(self.min_ts, other.min_ts);
         self.max_ts = cmp::max(self.max_ts, other.max_ts);
-        self.num_rows += other.num_rows;
-        self.num_puts += other.num_puts;
-        self.num_versions += other.num_versions;
+        self.puts_valid = self.puts_valid && other.puts_valid;
+        // Counters saturate instead of wrapping when too many SSTs are aggregated.
+        self.num_rows = self.num_rows.saturating_add(other.num_rows);
+        self.num_puts = self.num_puts.saturating_add(other.num_puts);
+        self.num_deletes = self.num_deletes.saturating_add(other.num_deletes);
+        self.num_rollbacks = self.num_rollbacks.saturating_add(other.num_rollbacks);
+        self.num_merges = self.num_merges.saturating_add(other.num_merges);
+        self.num_versions = self.num_versions.saturating_add(other.num_versions);
         self.max_row_versions = cmp::max(self.max_row_versions, other.max_row_versions);
-        self.num_errors += other.num_errors;
+        self.num_hot_rows = self.num_hot_rows.saturating_add(other.num_hot_rows);
+        self.num_ts_anomalies = self.num_ts_anomalies.saturating_add(other.num_ts_anomalies);
+        self.num_duplicate_versions =
+            self.num_duplicate_versions.saturating_add(other.num_duplicate_versions);
+        self.max_version_gap = cmp::max(self.max_version_gap, other.max_version_gap);
+        self.min_start_ts = cmp::min(self.min_start_ts, other.min_start_ts);
+        self.max_start_ts = cmp::max(self.max_start_ts, other.max_start_ts);
+        self.num_inlined_values = self.num_inlined_values.saturating_add(other.num_inlined_values);
+        self.num_pointer_values = self.num_pointer_values.saturating_add(other.num_pointer_values);
+        self.deletes_seen = self.deletes_seen || other.deletes_seen;
+        self.num_non_data_versions =
+            self.num_non_data_versions.saturating_add(other.num_non_data_versions);
+        self.total_row_key_len = self.total_row_key_len.saturating_add(other.total_row_key_len);
+        self.num_single_version_rows =
+            self.num_single_version_rows.saturating_add(other.num_single_version_rows);
+        self.num_fully_deleted_rows =
+            self.num_fully_deleted_rows.saturating_add(other.num_fully_deleted_rows);
+        self.num_pure_tombstone_rows =
+            self.num_pure_tombstone_rows.saturating_add(other.num_pure_tombstone_rows);
+        self.versions_below_safepoint =
+            self.versions_below_safepoint.saturating_add(other.versions_below_safepoint);
+        self.num_puts_below_safepoint =
+            self.num_puts_below_safepoint.saturating_add(other.num_puts_below_safepoint);
+        self.min_row_latest_ts = cmp::min(self.min_row_latest_ts, other.min_row_latest_ts);
+        for (h, o) in self.versions_histogram.iter_mut().zip(&other.versions_histogram) {
+            *h = h.saturating_add(*o);
+        }
+        if self.smallest_key.is_empty() ||
+           (!other.smallest_key.is_empty() && other.smallest_key < self.smallest_key) {
+            self.smallest_key = other.smallest_key.clone();
+            self.first_row_versions = other.first_row_versions;
+        }
+        if other.largest_key > self.largest_key {
+            self.largest_key = other.largest_key.clone();
+            self.last_row_versions = other.last_row_versions;
+        }
     }
 
-    pub fn encode(&self) -> HashMap<Vec<u8>, Vec<u8>> {
-        let items = [(PROP_MIN_TS, self.min_ts),
-                     (PROP_MAX_TS, self.max_ts),
-                     (PROP_NUM_ROWS, self.num_rows),
-                     (PROP_NUM_PUTS, self.num_puts),
-                     (PROP_NUM_VERSIONS, self.num_versions),
-                     (PROP_MAX_ROW_VERSIONS, self.max_row_versions),
-                     (PROP_NUM_ERRORS, self.num_errors)];
-        items.iter()
-            .map(|&(k, v)| {
-                let mut buf = Vec::with_capacity(8);
-                buf.encode_u64(v).unwrap();
-                (k.as_bytes().to_owned(), buf)
-            })
-            .collect()
+    /// Returns true if the last row of this SST continues as the first row of
+    /// `next`, whose key range follows this one. The versions of the row in
+    /// each SST are `last_row_versions` and `next.first_row_versions`.
+    pub fn shares_boundary_row(&self, next: &MvccProperties) -> bool {
+        !self.is_empty() && !next.is_empty() && !self.largest_key.is_empty() &&
+        self.largest_key == next.smallest_key
     }
 
-    pub fn decode<T: DecodeU64>(props: &T) -> Result<UserProperties, codec::Error> {
-        let mut res = UserProperties::new();
-        res.min_ts = try!(props.decode_u64(PROP_MIN_TS));
-        res.max_ts = try!(props.decode_u64(PROP_MAX_TS));
-        res.num_rows = try!(props.decode_u64(PROP_NUM_ROWS));
-        res.num_puts = try!(props.decode_u64(PROP_NUM_PUTS));
-        res.num_versions = try!(props.decode_u64(PROP_NUM_VERSIONS));
-        res.max_row_versions = try!(props.decode_u64(PROP_MAX_ROW_VERSIONS));
-        res.num_errors = try!(props.decode_u64(PROP_NUM_ERRORS));
-        Ok(res)
+    /// Aggregates `next`, whose key range follows this one, and stitches
+    /// the row straddling both of them if any.
+    ///
+    /// A row can be split into two adjacent SSTs, so `add` counts it twice
+    /// in `num_rows` and underestimates `max_row_versions`. Note that it
+    /// only works for non-overlapping SSTs in key order, e.g. SSTs in the
+    /// same level, and other per-row properties like `num_hot_rows` are not
+    /// corrected, except `versions_histogram`, `total_row_key_len` and
+    /// `num_single_version_rows`.
+    pub fn add_adjacent(&mut self, next: &MvccProperties) {
+        let straddle = self.shares_boundary_row(next);
+        let (num_rows, last_row_versions) = (self.num_rows, self.last_row_versions);
+        self.add(next);
+        if !straddle {
+            return;
+        }
+
+        let versions = last_row_versions.saturating_add(next.first_row_versions);
+        self.num_rows -= 1;
+        self.total_row_key_len =
+            self.total_row_key_len.saturating_sub(user_key_len(&next.smallest_key));
+        // The straddling row has at least two versions.
+        let single = (last_row_versions == 1) as u64 + (next.first_row_versions == 1) as u64;
+        self.num_single_version_rows = self.num_single_version_rows.saturating_sub(single);
+        self.max_row_versions = cmp::max(self.max_row_versions, versions);
+        if num_rows == 1 {
+            self.first_row_versions = versions;
+        }
+        if next.num_rows == 1 {
+            self.last_row_versions = versions;
+        }
+        // Older SSTs don't have the histogram.
+        let (a, b) = (versions_bucket(last_row_versions), versions_bucket(next.first_row_versions));
+        let hist = &mut self.versions_histogram;
+        if hist[a] > 0 && hist[b] > (a == b) as u64 {
+            hist[a] -= 1;
+            hist[b] -= 1;
+            hist[versions_bucket(versions)] += 1;
+        }
     }
-}
 
-pub trait DecodeU64 {
-    fn decode_u64(&self, k: &str) -> Result<u64, codec::Error>;
-}
+    /// Aggregates all properties from `iter` into one.
+    pub fn from_iter<I: IntoIterator<Item = MvccProperties>>(iter: I) -> MvccProperties {
+        let mut res = MvccProperties::new();
+        for props in iter {
+            res.add(&props);
+        }
+        res
+    }
 
-impl DecodeU64 for HashMap<Vec<u8>, Vec<u8>> {
-    fn decode_u64(&self, k: &str) -> Result<u64, codec::Error> {
-        match self.get(k.as_bytes()) {
-            Some(v) => v.as_slice().decode_u64(),
-            None => Err(codec::Error::KeyNotFound),
+    /// Returns the earliest commit ts, which is `min_ts` since keys carry commit ts.
+    pub fn earliest_commit_ts(&self) -> u64 {
+        self.min_ts
+    }
+
+    /// Returns the latest commit ts, which is `max_ts` since keys carry commit ts.
+    pub fn latest_commit_ts(&self) -> u64 {
+        self.max_ts
+    }
+
+    /// Returns the number of versions of the data, i.e. puts and deletes,
+    /// excluding lock and rollback records. It's the same as `num_versions`
+    /// if writes are not parsed, see `puts_valid`.
+    pub fn num_data_versions(&self) -> u64 {
+        self.num_versions.saturating_sub(self.num_non_data_versions)
+    }
+
+    /// Returns the number of versions not older than the collector's safe point.
+    pub fn versions_above_safepoint(&self) -> u64 {
+        self.num_versions.saturating_sub(self.versions_below_safepoint)
+    }
+
+    /// Returns the half-open range of ts of the versions, or `None` if there is
+    /// no version or the range isn't collected. The end saturates at `u64::MAX`,
+    /// which is never a commit ts, so the range is exact in practice.
+    pub fn ts_range(&self) -> Option<Range<u64>> {
+        if self.is_empty() || !self.is_field_valid(PropKey::MinTs) || self.min_ts > self.max_ts {
+            return None;
         }
+        Some(self.min_ts..self.max_ts.saturating_add(1))
     }
-}
 
-impl DecodeU64 for UserCollectedProperties {
-    fn decode_u64(&self, k: &str) -> Result<u64, codec::Error> {
-        match self.get(k.as_bytes()) {
-            Some(mut v) => v.decode_u64(),
-            None => Err(codec::Error::KeyNotFound),
+    /// Returns true if there may be versions not newer than `max_ts`, which is
+    /// always the case if the ts range isn't collected, e.g. with only `DELETE_RATIO`.
+    /// Empty properties have no version to overlap.
+    pub fn overlaps_ts(&self, max_ts: u64) -> bool {
+        if self.is_empty() {
+            return false;
         }
+        if !self.is_field_valid(PropKey::MinTs) {
+            return true;
+        }
+        self.min_ts <= max_ts
     }
-}
 
-pub struct UserPropertiesCollector {
-    props: UserProperties,
-    last_row: Vec<u8>,
-    row_versions: u64,
-}
+    /// Returns the estimated number of versions with ts in `[lo, hi]`, assuming
+    /// versions are evenly distributed in `[min_ts, max_ts]`. It's rounded up,
+    /// so it's never 0 if the window overlaps the range, and all the versions are
+    /// counted if the range isn't collected.
+    pub fn estimate_versions_in_window(&self, lo: u64, hi: u64) -> u64 {
+        if !self.is_empty() && lo <= hi && !self.is_field_valid(PropKey::MinTs) {
+            return self.num_versions;
+        }
+        if self.is_empty() || lo > hi || hi < self.min_ts || lo > self.max_ts {
+            return 0;
+        }
+        if lo <= self.min_ts && hi >= self.max_ts {
+            return self.num_versions;
+        }
+        let overlap = cmp::min(hi, self.max_ts) - cmp::max(lo, self.min_ts);
+        // Both ends are inclusive.
+        let ratio = (overlap as f64 + 1.0) / ((self.max_ts - self.min_ts) as f64 + 1.0);
+        let estimate = (self.num_versions as f64 * ratio).ceil() as u64;
+        cmp::min(estimate, self.num_versions)
+    }
 
-impl Default for UserPropertiesCollector {
-    fn default() -> UserPropertiesCollector {
-        UserPropertiesCollector {
-            props: UserProperties::new(),
-            last_row: Vec::new(),
-            row_versions: 0,
+    /// Returns the estimated size in bytes of the rows, given the average size
+    /// of a row. It's only a rough estimate for callers without `SizeProperties`,
+    /// which should be used instead if available, and saturates at `u64::MAX`.
+    pub fn approximate_size(&self, avg_row_bytes: u64) -> u64 {
+        self.num_rows.saturating_mul(avg_row_bytes)
+    }
+
+    /// Returns a ts that the commit ts of every version GC may collect from
+    /// the SST is below, whatever the safe point is. GC keeps the latest version
+    /// of a row unless it's a delete, a lock or a rollback record, so it's 0 if
+    /// all rows have one version and there are no such records, `max_ts` if
+    /// there are none, and `max_ts + 1` otherwise, which is also the case if
+    /// writes are not parsed, see `puts_valid`, as lock and rollback records
+    /// can't be ruled out. It's conservative: a version may still be kept by GC, and
+    /// `u64::MAX` is returned if `max_row_versions` and the ts range aren't
+    /// collected. Only the versions in the SST are considered, as a compaction
+    /// filter sees them.
+    ///
+    /// See `can_skip_gc` for how to tell whether the SST can be skipped by GC.
+    pub fn gc_eligible_below(&self) -> u64 {
+        if self.is_empty() {
+            return 0;
+        }
+        // Neither `max_row_versions` nor the ts range is collected without
+        // `MVCC_PROPERTIES`.
+        if !self.is_field_valid(PropKey::MaxRowVersions) {
+            return u64::MAX;
+        }
+        if self.has_deletes() || !self.puts_valid || self.num_non_data_versions > 0 {
+            return self.max_ts.saturating_add(1);
+        }
+        if self.max_row_versions > 1 {
+            self.max_ts
+        } else {
+            0
         }
     }
-}
 
-impl TablePropertiesCollector for UserPropertiesCollector {
-    fn add(&mut self, key: &[u8], value: &[u8], entry_type: DBEntryType, _: u64, _: u64) {
-        if !keys::validate_data_key(key) {
-            self.props.num_errors += 1;
-            return;
+    /// Returns true if GC with `safe_point` has nothing to collect from the SST,
+    /// i.e. `safe_point < min_ts` or `gc_eligible_below() <= min_ts`. It's false
+    /// if the ts range isn't collected, e.g. with only `DELETE_RATIO`.
+    pub fn can_skip_gc(&self, safe_point: u64) -> bool {
+        if self.is_empty() {
+            return true;
         }
+        if !self.is_field_valid(PropKey::MinTs) {
+            return false;
+        }
+        safe_point < self.min_ts || self.gc_eligible_below() <= self.min_ts
+    }
 
-        let (k, ts) = match types::split_encoded_key_on_ts(key) {
-            Ok((k, ts)) => (k, ts),
-            Err(_) => {
-                self.props.num_errors += 1;
-                return;
-            }
+    /// Returns true if the latest write is older than `now_ts` by more than
+    /// `cold_threshold`. It's never cold if `max_ts` is ahead of `now_ts`, or
+    /// if the ts range isn't collected.
+    pub fn is_cold(&self, now_ts: u64, cold_threshold: u64) -> bool {
+        if !self.is_empty() && !self.is_field_valid(PropKey::MinTs) {
+            return false;
+        }
+        match now_ts.checked_sub(self.max_ts) {
+            Some(age) => age > cold_threshold,
+            None => false,
+        }
+    }
+
+    /// Returns the estimated number of distinct rows. Unlike `num_rows`, rows
+    /// spanning several SSTs are counted once, but it falls back to `num_rows`
+    /// if the properties have no sketch of row keys.
+    pub fn estimated_distinct_rows(&self) -> u64 {
+        if self.key_hll.is_empty() {
+            return self.num_rows;
+        }
+        hll_estimate(&self.key_hll).round() as u64
+    }
+
+    /// Returns false if no user key starts with `prefix`. It may return true
+    /// even if none does, and always does if the properties have no bloom
+    /// filter or `prefix` is shorter than the prefixes in the filter.
+    pub fn may_contain_prefix(&self, prefix: &[u8]) -> bool {
+        let len = self.bloom_prefix_len as usize;
+        if self.prefix_bloom.is_empty() || prefix.len() < len {
+            return true;
+        }
+        bloom_contains(&self.prefix_bloom, &prefix[..len])
+    }
+
+    /// Returns the fraction of versions which are deletes.
+    pub fn delete_ratio(&self) -> f64 {
+        if self.num_versions == 0 {
+            return 0.0;
+        }
+        self.num_deletes as f64 / self.num_versions as f64
+    }
+
+    /// Returns the average number of versions per row, or 0.0 if there are no rows.
+    pub fn avg_versions_per_row(&self) -> f64 {
+        if self.num_rows == 0 {
+            return 0.0;
+        }
+        self.num_versions as f64 / self.num_rows as f64
+    }
+
+    /// Returns the average number of puts per row, or 0.0 if there are no rows.
+    pub fn avg_puts_per_row(&self) -> f64 {
+        if self.num_rows == 0 {
+            return 0.0;
+        }
+        self.num_puts as f64 / self.num_rows as f64
+    }
+
+    /// Returns the average length of user keys of rows, or 0.0 if there are no rows.
+    pub fn avg_key_len(&self) -> f64 {
+        if self.num_rows == 0 {
+            return 0.0;
+        }
+        self.total_row_key_len as f64 / self.num_rows as f64
+    }
+
+    /// Returns a score in `[0, 100]` of how much GC would reclaim, so that the
+    /// ranges worth GC the most can be found by scanning properties only:
+    ///
+    /// `100 * (0.6 * delete_ratio + 0.3 * old_ratio + 0.1 * hot_ratio)`
+    ///
+    /// `old_ratio` is the fraction of versions which are not the latest of their
+    /// rows, and `hot_ratio` is `max_row_versions - 1` over `DEFAULT_HOT_ROW_VERSIONS`,
+    /// capped at 1. Deletes weigh the most, since a delete and all the versions
+    /// it shadows can be removed. Deletes are not counted if `puts_valid` is false.
+    pub fn gc_score(&self) -> u8 {
+        if self.num_versions == 0 {
+            return 0;
+        }
+        let delete_ratio = if self.puts_valid {
+            cmp::min(self.num_deletes, self.num_versions) as f64 / self.num_versions as f64
+        } else {
+            0.0
+        };
+        // Rows are unknown if only counters are collected.
+        let old_ratio = if self.num_rows == 0 {
+            0.0
+        } else {
+            self.num_versions.saturating_sub(self.num_rows) as f64 / self.num_versions as f64
         };
+        let hot_versions = cmp::min(self.max_row_versions.saturating_sub(1),
+                                    DEFAULT_HOT_ROW_VERSIONS);
+        let hot_ratio = hot_versions as f64 / DEFAULT_HOT_ROW_VERSIONS as f64;
+        let score = 100.0 * (0.6 * delete_ratio + 0.3 * old_ratio + 0.1 * hot_ratio);
+        score.round() as u8
+    }
 
-        self.props.min_ts = cmp::min(self.props.min_ts, ts);
-        self.props.max_ts = cmp::max(self.props.max_ts, ts);
-        match entry_type {
-            DBEntryType::Put => self.props.num_versions += 1,
-            _ => return,
+    /// Returns true if the key range may overlap `[start, end)`, where an empty
+    /// `end` means no upper bound. Keys are data keys without ts, like
+    /// `smallest_key` and `largest_key`, and the range is unknown if they are empty.
+    pub fn covers_range(&self, start: &[u8], end: &[u8]) -> bool {
+        if self.is_empty() {
+            return false;
+        }
+        if self.smallest_key.is_empty() || self.largest_key.is_empty() {
+            return true;
+        }
+        (end.is_empty() || self.smallest_key.as_slice() < end) &&
+        self.largest_key.as_slice() >= start
+    }
+
+    /// Returns the changes from `older`, e.g. the properties of the same
+    /// range before a compaction.
+    pub fn diff(&self, older: &MvccProperties) -> MvccPropertiesDiff {
+        MvccPropertiesDiff {
+            num_rows: delta(self.num_rows, older.num_rows),
+            num_puts: delta(self.num_puts, older.num_puts),
+            num_versions: delta(self.num_versions, older.num_versions),
+            ts_range_expanded: self.min_ts < older.min_ts || self.max_ts > older.max_ts,
+        }
+    }
+
+    /// Returns true if there are more than `max_rows` rows.
+    /// `max_rows` of 0 means no limit.
+    pub fn should_split(&self, max_rows: u64) -> bool {
+        max_rows > 0 && self.num_rows > max_rows
+    }
+
+    /// Returns the estimated number of regions after splitting
+    /// into regions of at most `max_rows` rows.
+    pub fn estimated_regions(&self, max_rows: u64) -> u64 {
+        if !self.should_split(max_rows) {
+            return 1;
+        }
+        let n = self.num_rows / max_rows;
+        if self.num_rows % max_rows == 0 { n } else { n + 1 }
+    }
+
+    /// Returns at most `parts - 1` ordered keys to split the key range into
+    /// `parts` ranges of about the same number of rows.
+    ///
+    /// Only the boundary keys are known, so this assumes the rows are spread
+    /// evenly between `smallest_key` and `largest_key`, and interpolates linearly
+    /// over the 8 bytes after their common prefix. The split keys are strictly
+    /// between the boundary keys, but they may not be valid encoded keys. Fewer
+    /// keys are returned if there are fewer rows than `parts`, or the boundary
+    /// keys are too close to be split.
+    pub fn suggest_split_keys(&self, parts: usize) -> Vec<Vec<u8>> {
+        if self.smallest_key.is_empty() || self.smallest_key >= self.largest_key {
+            return vec![];
+        }
+        let prefix_len = self.smallest_key
+            .iter()
+            .zip(&self.largest_key)
+            .take_while(|&(a, b)| a == b)
+            .count();
+        let start = split_window(&self.smallest_key[prefix_len..]);
+        let end = split_window(&self.largest_key[prefix_len..]);
+        // There are only `end - start - 1` distinct keys strictly between the
+        // boundary keys, so `parts` is clamped before allocating.
+        let parts = cmp::min(cmp::min(parts as u64, self.num_rows), end - start);
+        if parts <= 1 {
+            return vec![];
+        }
+        // `start + (end - start) * i / parts` without overflow, computed as
+        // `step * i + rem * i / parts`, where the fraction is carried over.
+        // As `step >= 1`, every key is distinct.
+        let (step, rem) = ((end - start) / parts, (end - start) % parts);
+        let mut res: Vec<Vec<u8>> = Vec::with_capacity(parts as usize - 1);
+        let (mut v, mut frac) = (start, 0);
+        for _ in 1..parts {
+            v += step;
+            // `frac + rem >= parts` without overflow.
+            if rem >= parts - frac {
+                frac -= parts - rem;
+                v += 1;
+            } else {
+                frac += rem;
+            }
+            let mut key = Vec::with_capacity(prefix_len + number::U64_SIZE);
+            key.extend_from_slice(&self.smallest_key[..prefix_len]);
+            key.encode_u64(v).unwrap();
+            res.push(key);
+        }
+        res
+    }
+
+    fn get(&self, key: PropKey) -> u64 {
+        match key {
+            PropKey::MinTs => self.min_ts,
+            PropKey::MaxTs => self.max_ts,
+            PropKey::NumRows => self.num_rows,
+            PropKey::NumPuts => self.num_puts,
+            PropKey::PutsValid => self.puts_valid as u64,
+            PropKey::NumDeletes => self.num_deletes,
+            PropKey::NumRollbacks => self.num_rollbacks,
+            PropKey::NumMerges => self.num_merges,
+            PropKey::NumVersions => self.num_versions,
+            PropKey::MaxRowVersions => self.max_row_versions,
+            PropKey::NumHotRows => self.num_hot_rows,
+            PropKey::NumTsAnomalies => self.num_ts_anomalies,
+            PropKey::NumFullyDeletedRows => self.num_fully_deleted_rows,
+            PropKey::NumPureTombstoneRows => self.num_pure_tombstone_rows,
+            PropKey::VersionsBelowSafepoint => self.versions_below_safepoint,
+            PropKey::NumPutsBelowSafepoint => self.num_puts_below_safepoint,
+            PropKey::FirstRowVersions => self.first_row_versions,
+            PropKey::LastRowVersions => self.last_row_versions,
+            PropKey::MinRowLatestTs => self.min_row_latest_ts,
+            PropKey::MaxValueSize => self.max_value_size,
+            PropKey::EntryTypePuts => self.entry_type_puts,
+            PropKey::EntryTypeDeletes => self.entry_type_deletes,
+            PropKey::EntryTypeOther => self.entry_type_other,
+            PropKey::NumDuplicateVersions => self.num_duplicate_versions,
+            PropKey::MaxVersionGap => self.max_version_gap,
+            PropKey::MinStartTs => self.min_start_ts,
+            PropKey::MaxStartTs => self.max_start_ts,
+            PropKey::NumInlinedValues => self.num_inlined_values,
+            PropKey::NumPointerValues => self.num_pointer_values,
+            PropKey::HasDeletes => self.deletes_seen as u64,
+            PropKey::NumNonDataVersions => self.num_non_data_versions,
+            PropKey::TotalRowKeyLen => self.total_row_key_len,
+            PropKey::NumSingleVersionRows => self.num_single_version_rows,
+        }
+    }
+
+    fn set(&mut self, key: PropKey, v: u64) {
+        match key {
+            PropKey::MinTs => self.min_ts = v,
+            PropKey::MaxTs => self.max_ts = v,
+            PropKey::NumRows => self.num_rows = v,
+            PropKey::NumPuts => self.num_puts = v,
+            PropKey::PutsValid => self.puts_valid = v != 0,
+            PropKey::NumDeletes => self.num_deletes = v,
+            PropKey::NumRollbacks => self.num_rollbacks = v,
+            PropKey::NumMerges => self.num_merges = v,
+            PropKey::NumVersions => self.num_versions = v,
+            PropKey::MaxRowVersions => self.max_row_versions = v,
+            PropKey::NumHotRows => self.num_hot_rows = v,
+            PropKey::NumTsAnomalies => self.num_ts_anomalies = v,
+            PropKey::NumFullyDeletedRows => self.num_fully_deleted_rows = v,
+            PropKey::NumPureTombstoneRows => self.num_pure_tombstone_rows = v,
+            PropKey::VersionsBelowSafepoint => self.versions_below_safepoint = v,
+            PropKey::NumPutsBelowSafepoint => self.num_puts_below_safepoint = v,
+            PropKey::FirstRowVersions => self.first_row_versions = v,
+            PropKey::LastRowVersions => self.last_row_versions = v,
+            PropKey::MinRowLatestTs => self.min_row_latest_ts = v,
+            PropKey::MaxValueSize => self.max_value_size = v,
+            PropKey::EntryTypePuts => self.entry_type_puts = v,
+            PropKey::EntryTypeDeletes => self.entry_type_deletes = v,
+            PropKey::EntryTypeOther => self.entry_type_other = v,
+            PropKey::NumDuplicateVersions => self.num_duplicate_versions = v,
+            PropKey::MaxVersionGap => self.max_version_gap = v,
+            PropKey::MinStartTs => self.min_start_ts = v,
+            PropKey::MaxStartTs => self.max_start_ts = v,
+            PropKey::NumInlinedValues => self.num_inlined_values = v,
+            PropKey::NumPointerValues => self.num_pointer_values = v,
+            PropKey::HasDeletes => self.deletes_seen = v != 0,
+            PropKey::NumNonDataVersions => self.num_non_data_versions = v,
+            PropKey::TotalRowKeyLen => self.total_row_key_len = v,
+            PropKey::NumSingleVersionRows => self.num_single_version_rows = v,
+        }
+    }
+
+    pub fn encode(&self) -> BTreeMap<Vec<u8>, Vec<u8>> {
+        self.encode_with_version(CURRENT_PROP_VERSION)
+    }
+
+    /// Dumps all fields as a JSON object for tools, keys are escaped and
+    /// sketches are in hex. Use `encode` for storing the properties instead.
+    pub fn to_json(&self) -> String {
+        let hex = |data: &[u8]| data.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+        let mut obj = Map::new();
+        for &k in PROP_KEYS {
+            // Keys are named after the fields.
+            let name = k.name().trim_left_matches("tikv.").to_owned();
+            obj.insert(name, Value::from(self.get(k)));
         }
+        // It's encoded as an integer, but is a bool.
+        obj.insert("puts_valid".to_owned(), Value::from(self.puts_valid));
+        obj.insert("versions_histogram".to_owned(),
+                   Value::from(self.versions_histogram.to_vec()));
+        obj.insert("smallest_key".to_owned(), Value::from(escape(&self.smallest_key)));
+        obj.insert("largest_key".to_owned(), Value::from(escape(&self.largest_key)));
+        obj.insert("key_hll".to_owned(), Value::from(hex(&self.key_hll)));
+        obj.insert("prefix_bloom".to_owned(), Value::from(hex(&self.prefix_bloom)));
+        obj.insert("bloom_prefix_len".to_owned(), Value::from(self.bloom_prefix_len));
+        let flags = match self.collected_flags {
+            Some(flags) => Value::from(flags.bits()),
+            None => Value::Null,
+        };
+        obj.insert("collected_flags".to_owned(), flags);
+        Value::Object(obj).to_string()
+    }
 
-        if k != self.last_row.as_slice() {
-            self.props.num_rows += 1;
-            self.row_versions = 1;
-            self.last_row.clear();
-            self.last_row.extend_from_slice(k);
+    fn encode_with_version(&self, version: u32) -> BTreeMap<Vec<u8>, Vec<u8>> {
+        let encode_num = if version >= PROP_VERSION_V2 {
+            encode_var_u64
         } else {
-            self.row_versions += 1;
+            encode_u64
+        };
+        // Since version 2, fields with default values are omitted to save space,
+        // as decoding takes absent fields as the defaults.
+        let mut res: BTreeMap<_, _> = PROP_KEYS.iter()
+            .filter(|&&k| version < PROP_VERSION_V2 || k.default_value() != Some(self.get(k)))
+            .map(|&k| (k.as_bytes().to_owned(), encode_num(self.get(k))))
+            .collect();
+        // The version is always fixed-size, so it can be decoded before knowing it.
+        res.insert(PROP_VERSION.as_bytes().to_owned(), encode_u64(version as u64));
+        // The flags are fixed-size as they used to be written by `UserProperties`.
+        if let Some(flags) = self.collected_flags {
+            res.insert(PROP_COLLECTED_FLAGS.as_bytes().to_owned(),
+                       encode_u64(flags.bits() as u64));
+        }
+        res.insert(PROP_SMALLEST_KEY.as_bytes().to_owned(),
+                   encode_compact_bytes(&self.smallest_key));
+        res.insert(PROP_LARGEST_KEY.as_bytes().to_owned(),
+                   encode_compact_bytes(&self.largest_key));
+        if self.versions_histogram.iter().any(|&n| n > 0) {
+            let mut buf = Vec::with_capacity(VERSIONS_HISTOGRAM_BUCKETS * number::U64_SIZE);
+            for &n in &self.versions_histogram {
+                buf.encode_u64(n).unwrap();
+            }
+            res.insert(PROP_VERSIONS_HISTOGRAM.as_bytes().to_owned(),
+                       encode_compact_bytes(&buf));
+        }
+        if !self.key_hll.is_empty() {
+            res.insert(PROP_KEY_HLL.as_bytes().to_owned(),
+                       encode_compact_bytes(&self.key_hll));
+        }
+        if !self.prefix_bloom.is_empty() {
+            res.insert(PROP_KEY_PREFIX_BLOOM.as_bytes().to_owned(),
+                       encode_compact_bytes(&self.prefix_bloom));
+            res.insert(PROP_KEY_PREFIX_LEN.as_bytes().to_owned(),
+                       encode_num(self.bloom_prefix_len));
         }
-        if self.row_versions > self.props.max_row_versions {
-            self.props.max_row_versions = self.row_versions;
+        if version >= PROP_VERSION_V3 {
+            let crc = mvcc_checksum(&res);
+            res.insert(PROP_CRC.as_bytes().to_owned(), encode_u64(crc as u64));
         }
+        res
+    }
 
-        let v = match Write::parse(value) {
-            Ok(v) => v,
-            Err(_) => {
-                self.props.num_errors += 1;
-                return;
+    /// Decodes only `field`, without decoding the rest or verifying the checksum.
+    pub fn decode_field<T: DecodeU64>(props: &T, field: PropKey) -> Result<u64, codec::Error> {
+        match try!(decode_prop_version(props)) {
+            PROP_VERSION_V1 => field.decode(props),
+            PROP_VERSION_V2 | PROP_VERSION_V3 => field.decode(&VarU64Properties(props)),
+            v => {
+                Err(codec::Error::InvalidDataType(format!("invalid properties version {}", v)))
             }
+        }
+    }
+
+    pub fn decode<T: DecodeU64>(props: &T) -> Result<MvccProperties, codec::Error> {
+        let mut res = try!(MvccProperties::decode_versioned(props));
+        res.collected_flags = match try!(props.try_decode_u64(PROP_COLLECTED_FLAGS)) {
+            Some(v) if v <= u32::MAX as u64 => Some(PropertiesFlags::from_bits(v as u32)),
+            Some(v) => return Err(codec::Error::InvalidDataType(format!("invalid flags {}", v))),
+            None => None,
         };
+        Ok(res)
+    }
 
-        if v.write_type == WriteType::Put {
-            self.props.num_puts += 1;
+    fn decode_versioned<T: DecodeU64>(props: &T) -> Result<MvccProperties, codec::Error> {
+        match try!(decode_prop_version(props)) {
+            PROP_VERSION_V1 => MvccProperties::decode_fields(props),
+            PROP_VERSION_V2 => MvccProperties::decode_fields(&VarU64Properties(props)),
+            PROP_VERSION_V3 => {
+                if try!(props.decode_u64(PROP_CRC)) != mvcc_checksum(props) as u64 {
+                    return Err(codec::Error::ChecksumMismatch);
+                }
+                MvccProperties::decode_fields(&VarU64Properties(props))
+            }
+            v => {
+                Err(codec::Error::InvalidDataType(format!("invalid properties version {}", v)))
+            }
         }
     }
 
-    fn finish(&mut self) -> HashMap<Vec<u8>, Vec<u8>> {
-        self.props.encode()
+    /// Encodes the properties as a sequence of compact-bytes encoded key/value
+    /// pairs in key order, which doesn't depend on RocksDB's properties map.
+    pub fn encode_to_bytes(&self) -> Vec<u8> {
+        let mut buf = vec![];
+        for (k, v) in self.encode() {
+            buf.encode_compact_bytes(&k).unwrap();
+            buf.encode_compact_bytes(&v).unwrap();
+        }
+        buf
     }
-}
 
-#[derive(Default)]
-pub struct UserPropertiesCollectorFactory {}
+    /// Decodes properties encoded by `encode_to_bytes`.
+    pub fn decode_from_bytes(mut data: &[u8]) -> Result<MvccProperties, codec::Error> {
+        let mut props = BTreeMap::new();
+        while !data.is_empty() {
+            let k = try!(data.decode_compact_bytes());
+            let v = try!(data.decode_compact_bytes());
+            props.insert(k, v);
+        }
+        MvccProperties::decode(&props)
+    }
 
-impl TablePropertiesCollectorFactory for UserPropertiesCollectorFactory {
-    fn create_table_properties_collector(&mut self, _: u32) -> Box<TablePropertiesCollector> {
-        Box::new(UserPropertiesCollector::default())
+    /// Decodes the fixed-size histogram stored under `tikv.versions_histogram`.
+    pub fn decode_versions_histogram(mut data: &[u8])
+                                     -> Result<[u64; VERSIONS_HISTOGRAM_BUCKETS], codec::Error> {
+        if data.len() != VERSIONS_HISTOGRAM_BUCKETS * number::U64_SIZE {
+            let msg = format!("invalid versions histogram len {}", data.len());
+            return Err(codec::Error::InvalidDataType(msg));
+        }
+        let mut res = [0; VERSIONS_HISTOGRAM_BUCKETS];
+        for n in &mut res {
+            *n = try!(data.decode_u64());
+        }
+        Ok(res)
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use rocksdb::{DBEntryType, TablePropertiesCollector};
-    use storage::Key;
-    use storage::mvcc::{Write, WriteType};
-    use raftstore::store::keys;
-    use super::{UserProperties, UserPropertiesCollector};
+    fn decode_fields<T: DecodeU64>(props: &T) -> Result<MvccProperties, codec::Error> {
+        let mut res = MvccProperties::new();
+        for &k in PROP_KEYS {
+            res.set(k, try!(k.decode(props)));
+        }
+        res.smallest_key = try!(decode_bytes_or_empty(props, PROP_SMALLEST_KEY));
+        res.largest_key = try!(decode_bytes_or_empty(props, PROP_LARGEST_KEY));
+        let histogram = try!(decode_bytes_or_empty(props, PROP_VERSIONS_HISTOGRAM));
+        if !histogram.is_empty() {
+            res.versions_histogram = try!(MvccProperties::decode_versions_histogram(&histogram));
+        }
+        res.key_hll = try!(decode_bytes_or_empty(props, PROP_KEY_HLL));
+        res.prefix_bloom = try!(decode_bytes_or_empty(props, PROP_KEY_PREFIX_BLOOM));
+        try!(res.check_sketches());
+        if !res.prefix_bloom.is_empty() {
+            res.bloom_prefix_len = try!(props.decode_u64(PROP_KEY_PREFIX_LEN));
+        }
+        Ok(res)
+    }
 
-    #[test]
-    fn test_user_properties() {
-        let cases = [("ab", 2, WriteType::Put, DBEntryType::Put),
-                     ("ab", 1, WriteType::Delete, DBEntryType::Put),
-                     ("ab", 1, WriteType::Delete, DBEntryType::Delete),
-                     ("cd", 5, WriteType::Delete, DBEntryType::Put),
-                     ("cd", 4, WriteType::Put, DBEntryType::Put),
-                     ("cd", 3, WriteType::Put, DBEntryType::Put),
-                     ("ef", 6, WriteType::Put, DBEntryType::Put),
-                     ("ef", 6, WriteType::Put, DBEntryType::Delete),
-                     ("gh", 7, WriteType::Delete, DBEntryType::Put)];
-        let mut collector = UserPropertiesCollector::default();
-        for &(key, ts, write_type, entry_type) in &cases {
-            let k = Key::from_raw(key.as_bytes()).append_ts(ts);
-            let k = keys::data_key(k.encoded());
-            let v = Write::new(write_type, ts, None).to_bytes();
-            collector.add(&k, &v, entry_type, 0, 0);
+    // The sketches are of fixed sizes if collected.
+    fn check_sketches(&self) -> Result<(), codec::Error> {
+        if !self.key_hll.is_empty() && self.key_hll.len() != HLL_REGISTERS {
+            return Err(codec::Error::InvalidDataType(format!("invalid key hll len {}",
+                                                             self.key_hll.len())));
         }
-        collector.add(b"error", b"error", DBEntryType::Put, 0, 0);
+        if !self.prefix_bloom.is_empty() && self.prefix_bloom.len() != BLOOM_BYTES {
+            let msg = format!("invalid key prefix bloom len {}", self.prefix_bloom.len());
+            return Err(codec::Error::InvalidDataType(msg));
+        }
+        Ok(())
+    }
 
-        let props = UserProperties::decode(&collector.finish()).unwrap();
-        assert_eq!(props.min_ts, 1);
-        assert_eq!(props.max_ts, 7);
+    /// Encodes the properties for sending to other nodes, which may run other
+    /// versions. Unlike `encode`, it doesn't depend on RocksDB's properties map:
+    /// it's the wire version followed by the fields in a fixed order, and lists
+    /// are prefixed with their lengths, so fields can be appended compatibly.
+    pub fn to_wire(&self) -> Vec<u8> {
+        let mut buf = vec![];
+        buf.encode_u64(WIRE_VERSION as u64).unwrap();
+        buf.encode_var_u64(PROP_KEYS.len() as u64).unwrap();
+        for &k in PROP_KEYS {
+            buf.encode_var_u64(self.get(k)).unwrap();
+        }
+        // 0 if there are no flags, otherwise the flags plus 1.
+        let flags = self.collected_flags.map_or(0, |flags| flags.bits() as u64 + 1);
+        buf.encode_var_u64(flags).unwrap();
+        buf.encode_var_u64(VERSIONS_HISTOGRAM_BUCKETS as u64).unwrap();
+        for &n in &self.versions_histogram {
+            buf.encode_var_u64(n).unwrap();
+        }
+        buf.encode_compact_bytes(&self.smallest_key).unwrap();
+        buf.encode_compact_bytes(&self.largest_key).unwrap();
+        buf.encode_compact_bytes(&self.key_hll).unwrap();
+        buf.encode_compact_bytes(&self.prefix_bloom).unwrap();
+        buf.encode_var_u64(self.bloom_prefix_len).unwrap();
+        buf
+    }
+
+    /// Decodes properties encoded by `to_wire`. Integer fields unknown to this
+    /// version are skipped, and those the sender doesn't know take the default
+    /// values, or fail if they have none. Trailing data appended by newer
+    /// versions is ignored.
+    pub fn from_wire(mut data: &[u8]) -> Result<MvccProperties, codec::Error> {
+        let version = try!(data.decode_u64());
+        if version > WIRE_VERSION as u64 {
+            let version = cmp::min(version, u32::MAX as u64) as u32;
+            return Err(codec::Error::UnsupportedPropVersion(version));
+        }
+        if version != WIRE_VERSION as u64 {
+            return Err(codec::Error::InvalidDataType(format!("invalid wire version {}",
+                                                             version)));
+        }
+        let mut res = MvccProperties::new();
+        let num_fields = try!(data.decode_var_u64());
+        for i in 0..num_fields {
+            let v = try!(data.decode_var_u64());
+            if let Some(&k) = PROP_KEYS.get(i as usize) {
+                res.set(k, v);
+            }
+        }
+        for &k in PROP_KEYS.iter().skip(num_fields as usize) {
+            match k.default_value() {
+                Some(v) => res.set(k, v),
+                None => {
+                    return Err(codec::Error::InvalidDataType(format!("missing {}", k.name())))
+                }
+            }
+        }
+        res.collected_flags = match try!(data.decode_var_u64()) {
+            0 => None,
+            v if v - 1 <= u32::MAX as u64 => Some(PropertiesFlags::from_bits((v - 1) as u32)),
+            v => return Err(codec::Error::InvalidDataType(format!("invalid flags {}", v - 1))),
+        };
+        let num_buckets = try!(data.decode_var_u64());
+        for i in 0..num_buckets {
+            let n = try!(data.decode_var_u64());
+            if let Some(bucket) = res.versions_histogram.get_mut(i as usize) {
+                *bucket = n;
+            }
+        }
+        res.smallest_key = try!(data.decode_compact_bytes());
+        res.largest_key = try!(data.decode_compact_bytes());
+        res.key_hll = try!(data.decode_compact_bytes());
+        res.prefix_bloom = try!(data.decode_compact_bytes());
+        res.bloom_prefix_len = try!(data.decode_var_u64());
+        try!(res.check_sketches());
+        Ok(res)
+    }
+}
+
+impl FromIterator<MvccProperties> for MvccProperties {
+    fn from_iter<I: IntoIterator<Item = MvccProperties>>(iter: I) -> MvccProperties {
+        MvccProperties::from_iter(iter)
+    }
+}
+
+/// `MvccPropertiesDiff` holds the changes between two `MvccProperties`,
+/// which are negative if the counters shrink.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MvccPropertiesDiff {
+    pub num_rows: i64,
+    pub num_puts: i64,
+    pub num_versions: i64,
+    pub ts_range_expanded: bool, // Whether there are versions out of the older ts range.
+}
+
+// Returns `new - old`, saturated to the range of i64.
+fn delta(new: u64, old: u64) -> i64 {
+    if new >= old {
+        cmp::min(new - old, i64::MAX as u64) as i64
+    } else {
+        -(cmp::min(old - new, i64::MAX as u64) as i64)
+    }
+}
+
+impl fmt::Display for MvccProperties {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "rows={} puts={} deletes={} versions={} ts=[{},{}] max_row_versions={}",
+               self.num_rows,
+               self.num_puts,
+               self.num_deletes,
+               self.num_versions,
+               self.min_ts,
+               self.max_ts,
+               self.max_row_versions)
+    }
+}
+
+/// Decodes and aggregates the MVCC properties of many SSTs.
+pub struct MvccPropertiesAggregator {
+    props: MvccProperties,
+}
+
+impl MvccPropertiesAggregator {
+    pub fn new() -> MvccPropertiesAggregator {
+        MvccPropertiesAggregator { props: MvccProperties::new() }
+    }
+
+    pub fn push<T: DecodeU64>(&mut self, props: &T) -> Result<(), codec::Error> {
+        let props = try!(MvccProperties::decode(props));
+        self.props.add(&props);
+        Ok(())
+    }
+
+    pub fn finish(self) -> MvccProperties {
+        self.props
+    }
+}
+
+/// Aggregates the encoded MVCC properties resolved by `futures`, e.g. read
+/// from many regions by RPC, folding them as they arrive. It fails on the
+/// first error. See `MvccPropertiesAggregator` for synchronous callers.
+pub fn aggregate_properties<I>(futures: I) -> BoxFuture<MvccProperties, codec::Error>
+    where I: IntoIterator,
+          I::Item: Future<Item = HashMap<Vec<u8>, Vec<u8>>, Error = codec::Error> + Send + 'static
+{
+    stream::futures_unordered(futures)
+        .fold(MvccPropertiesAggregator::new(),
+              |mut aggregator, props| aggregator.push(&props).map(|_| aggregator))
+        .map(|aggregator| aggregator.finish())
+        .boxed()
+}
+
+/// Filters out properties which only contain versions newer than `opts.max_ts`.
+pub fn filter_properties<'a>(props: &'a [MvccProperties],
+                             opts: &GetPropertiesOptions)
+                             -> Vec<&'a MvccProperties> {
+    let max_ts = opts.max_ts.unwrap_or(u64::MAX);
+    props.iter().filter(|p| p.overlaps_ts(max_ts)).collect()
+}
+
+/// Returns the minimal `min_ts` of the properties, i.e. the ts of the oldest
+/// version in all SSTs, or `u64::MAX` if there is none. Empty properties are
+/// skipped, as their `min_ts` is meaningless.
+pub fn oldest_ts<'a, I>(props: I) -> u64
+    where I: IntoIterator<Item = &'a MvccProperties>
+{
+    props.into_iter()
+        .filter(|p| !p.is_empty())
+        .fold(u64::MAX, |ts, p| cmp::min(ts, p.min_ts))
+}
+
+/// Merges the encoded MVCC properties of two SSTs into the encoded aggregate.
+/// It's only a convenience for callers holding encoded properties: both are fully
+/// decoded, combined by `MvccProperties::add`, and the result is encoded again,
+/// so it costs as much as doing so by hand. Other keys are dropped.
+pub fn decode_and_merge(a: &HashMap<Vec<u8>, Vec<u8>>,
+                        b: &HashMap<Vec<u8>, Vec<u8>>)
+                        -> Result<HashMap<Vec<u8>, Vec<u8>>, codec::Error> {
+    let mut res = try!(MvccProperties::decode(a));
+    res.add(&try!(MvccProperties::decode(b)));
+    Ok(res.encode().into_iter().collect())
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct UserProperties {
+    pub num_errors: u64, // The number of entries failed to parse.
+    // Whether any value failed to parse with `CollectorOptions::strict`.
+    pub fatal_errors: bool,
+    // Whether errors exceed `CollectorOptions::suspect_error_ratio` of entries.
+    pub suspect: bool,
+    pub num_non_data_keys: u64, // The number of entries whose keys are not data keys.
+    pub cf_name: String, // The column family the properties are collected from, empty if unknown.
+    pub cf_id: Option<u32>, // The column family id passed to the collector factory.
+    pub region_id: Option<u64>, // The region the SST belongs to, if the factory is given one.
+    pub entry_count: u64, // The number of entries passed to the collector.
+    pub collect_nanos: Option<u64>, // The time spent on collecting, only measured with `TIMING`.
+    // The first valid data key added with ts, only recorded with `FIRST_KEY`. Unlike
+    // `MvccProperties::smallest_key`, it's the first key in the order of adding.
+    pub first_key: Option<Vec<u8>>,
+    // The number of distinct user key prefixes of rows, only counted with
+    // `CollectorOptions::distinct_prefix_len`.
+    pub distinct_prefixes: Option<u64>,
+    pub mvcc: MvccProperties,
+    pub lock: Option<LockProperties>, // Only collected in `CfMode::Lock`.
+    pub default_cf: Option<DefaultCfProperties>, // Only collected in `CfMode::Default`.
+    pub raw: Option<RawProperties>, // Only collected in `CfMode::Raw`.
+}
+
+impl UserProperties {
+    pub fn new() -> UserProperties {
+        UserProperties {
+            num_errors: 0,
+            fatal_errors: false,
+            suspect: false,
+            num_non_data_keys: 0,
+            cf_name: String::new(),
+            cf_id: None,
+            region_id: None,
+            entry_count: 0,
+            collect_nanos: None,
+            first_key: None,
+            distinct_prefixes: None,
+            mvcc: MvccProperties::new(),
+            lock: None,
+            default_cf: None,
+            raw: None,
+        }
+    }
+
+    pub fn encode(&self) -> BTreeMap<Vec<u8>, Vec<u8>> {
+        let mut res = self.mvcc.encode();
+        // Both are absent if 0, as they usually are.
+        if self.num_errors > 0 {
+            res.insert(PROP_NUM_ERRORS.as_bytes().to_owned(), encode_u64(self.num_errors));
+        }
+        if self.fatal_errors {
+            res.insert(PROP_FATAL_ERRORS.as_bytes().to_owned(), encode_u64(1));
+        }
+        if self.suspect {
+            res.insert(PROP_SUSPECT.as_bytes().to_owned(), encode_u64(1));
+        }
+        if self.num_non_data_keys > 0 {
+            res.insert(PROP_NUM_NON_DATA_KEYS.as_bytes().to_owned(),
+                       encode_u64(self.num_non_data_keys));
+        }
+        if !self.cf_name.is_empty() {
+            res.insert(PROP_CF_NAME.as_bytes().to_owned(),
+                       encode_compact_bytes(self.cf_name.as_bytes()));
+        }
+        if let Some(cf_id) = self.cf_id {
+            res.insert(PROP_CF_ID.as_bytes().to_owned(), encode_u64(cf_id as u64));
+        }
+        if let Some(region_id) = self.region_id {
+            res.insert(PROP_REGION_ID.as_bytes().to_owned(), encode_u64(region_id));
+        }
+        res.insert(PROP_ENTRY_COUNT.as_bytes().to_owned(), encode_u64(self.entry_count));
+        if let Some(nanos) = self.collect_nanos {
+            res.insert(PROP_COLLECT_NANOS.as_bytes().to_owned(), encode_u64(nanos));
+        }
+        if let Some(n) = self.distinct_prefixes {
+            res.insert(PROP_DISTINCT_PREFIXES.as_bytes().to_owned(), encode_u64(n));
+        }
+        if let Some(ref key) = self.first_key {
+            res.insert(PROP_FIRST_DATA_KEY.as_bytes().to_owned(),
+                       encode_compact_bytes(key));
+        }
+        if let Some(ref lock) = self.lock {
+            res.extend(lock.encode());
+        }
+        if let Some(ref default_cf) = self.default_cf {
+            res.extend(default_cf.encode());
+        }
+        if let Some(ref raw) = self.raw {
+            res.extend(raw.encode());
+        }
+        res
+    }
+
+    pub fn decode<T: DecodeU64>(props: &T) -> Result<UserProperties, codec::Error> {
+        let mut res = UserProperties::new();
+        res.num_errors = try!(props.decode_u64_or(PROP_NUM_ERRORS, 0));
+        res.fatal_errors = try!(props.decode_u64_or(PROP_FATAL_ERRORS, 0)) != 0;
+        res.suspect = try!(props.decode_u64_or(PROP_SUSPECT, 0)) != 0;
+        res.num_non_data_keys = try!(props.decode_u64_or(PROP_NUM_NON_DATA_KEYS, 0));
+        let cf_name = try!(decode_bytes_or_empty(props, PROP_CF_NAME));
+        res.cf_name = try!(String::from_utf8(cf_name));
+        res.cf_id = match try!(props.try_decode_u64(PROP_CF_ID)) {
+            Some(v) if v <= u32::MAX as u64 => Some(v as u32),
+            Some(v) => return Err(codec::Error::InvalidDataType(format!("invalid cf id {}", v))),
+            None => None,
+        };
+        res.region_id = try!(props.try_decode_u64(PROP_REGION_ID));
+        res.entry_count = try!(props.decode_u64_or(PROP_ENTRY_COUNT, 0));
+        res.collect_nanos = try!(props.try_decode_u64(PROP_COLLECT_NANOS));
+        res.distinct_prefixes = try!(props.try_decode_u64(PROP_DISTINCT_PREFIXES));
+        res.first_key = match props.decode_bytes(PROP_FIRST_DATA_KEY) {
+            Ok(v) => Some(v),
+            Err(codec::Error::KeyNotFound) => None,
+            Err(e) => return Err(e),
+        };
+        // The MVCC block is absent if it is not collected.
+        match props.decode_u64(PropKey::MinTs.name()) {
+            Err(codec::Error::KeyNotFound) => {}
+            _ => res.mvcc = try!(MvccProperties::decode(props)),
+        }
+        match props.decode_u64(PROP_NUM_LOCKS) {
+            Err(codec::Error::KeyNotFound) => {}
+            _ => res.lock = Some(try!(LockProperties::decode(props))),
+        }
+        match props.decode_u64(PROP_TOTAL_DEFAULT_SIZE) {
+            Err(codec::Error::KeyNotFound) => {}
+            _ => res.default_cf = Some(try!(DefaultCfProperties::decode(props))),
+        }
+        match props.decode_u64(PROP_RAW_NUM_KEYS) {
+            Err(codec::Error::KeyNotFound) => {}
+            _ => res.raw = Some(try!(RawProperties::decode(props))),
+        }
+        Ok(res)
+    }
+
+    /// Returns true if the SST is likely corrupted, as too many entries failed
+    /// to parse, see `CollectorOptions::suspect_error_ratio`.
+    pub fn is_suspect(&self) -> bool {
+        self.suspect
+    }
+
+    pub fn decode_num_errors<T: DecodeU64>(props: &T) -> Result<u64, codec::Error> {
+        props.decode_u64_or(PROP_NUM_ERRORS, 0)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct LockProperties {
+    pub num_locks: u64, // The number of locks.
+    pub min_ts: u64, // The minimal start ts of all locks.
+    pub max_ts: u64, // The maximal start ts of all locks.
+}
+
+impl LockProperties {
+    pub fn new() -> LockProperties {
+        LockProperties {
+            num_locks: 0,
+            min_ts: u64::MAX,
+            max_ts: u64::MIN,
+        }
+    }
+
+    pub fn add(&mut self, other: &LockProperties) {
+        if other.num_locks == 0 {
+            return;
+        }
+        self.num_locks = self.num_locks.saturating_add(other.num_locks);
+        self.min_ts = cmp::min(self.min_ts, other.min_ts);
+        self.max_ts = cmp::max(self.max_ts, other.max_ts);
+    }
+
+    pub fn encode(&self) -> BTreeMap<Vec<u8>, Vec<u8>> {
+        let items = [(PROP_NUM_LOCKS, self.num_locks),
+                     (PROP_MIN_LOCK_TS, self.min_ts),
+                     (PROP_MAX_LOCK_TS, self.max_ts)];
+        items.iter()
+            .map(|&(k, v)| (k.as_bytes().to_owned(), encode_u64(v)))
+            .collect()
+    }
+
+    pub fn decode<T: DecodeU64>(props: &T) -> Result<LockProperties, codec::Error> {
+        let mut res = LockProperties::new();
+        res.num_locks = try!(props.decode_u64(PROP_NUM_LOCKS));
+        res.min_ts = try!(props.decode_u64(PROP_MIN_LOCK_TS));
+        res.max_ts = try!(props.decode_u64(PROP_MAX_LOCK_TS));
+        Ok(res)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct DefaultCfProperties {
+    pub total_default_size: u64, // The total size of all values.
+}
+
+impl DefaultCfProperties {
+    pub fn add(&mut self, other: &DefaultCfProperties) {
+        self.total_default_size = self.total_default_size.saturating_add(other.total_default_size);
+    }
+
+    pub fn encode(&self) -> BTreeMap<Vec<u8>, Vec<u8>> {
+        let mut res = BTreeMap::new();
+        res.insert(PROP_TOTAL_DEFAULT_SIZE.as_bytes().to_owned(),
+                   encode_u64(self.total_default_size));
+        res
+    }
+
+    pub fn decode<T: DecodeU64>(props: &T) -> Result<DefaultCfProperties, codec::Error> {
+        let mut res = DefaultCfProperties::default();
+        res.total_default_size = try!(props.decode_u64(PROP_TOTAL_DEFAULT_SIZE));
+        Ok(res)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct RawProperties {
+    pub num_keys: u64, // The number of keys.
+    pub total_bytes: u64, // The total size of all keys and values.
+}
+
+impl RawProperties {
+    pub fn add(&mut self, other: &RawProperties) {
+        self.num_keys = self.num_keys.saturating_add(other.num_keys);
+        self.total_bytes = self.total_bytes.saturating_add(other.total_bytes);
+    }
+
+    pub fn encode(&self) -> BTreeMap<Vec<u8>, Vec<u8>> {
+        let items = [(PROP_RAW_NUM_KEYS, self.num_keys),
+                     (PROP_RAW_TOTAL_BYTES, self.total_bytes)];
+        items.iter()
+            .map(|&(k, v)| (k.as_bytes().to_owned(), encode_u64(v)))
+            .collect()
+    }
+
+    pub fn decode<T: DecodeU64>(props: &T) -> Result<RawProperties, codec::Error> {
+        let mut res = RawProperties::default();
+        res.num_keys = try!(props.decode_u64(PROP_RAW_NUM_KEYS));
+        res.total_bytes = try!(props.decode_u64(PROP_RAW_TOTAL_BYTES));
+        Ok(res)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SizeProperties {
+    pub total_key_size: u64, // The total size of all keys.
+    pub total_value_size: u64, // The total size of all values.
+}
+
+impl SizeProperties {
+    pub fn total_size(&self) -> u64 {
+        self.total_key_size.saturating_add(self.total_value_size)
+    }
+
+    pub fn add(&mut self, other: &SizeProperties) {
+        self.total_key_size = self.total_key_size.saturating_add(other.total_key_size);
+        self.total_value_size = self.total_value_size.saturating_add(other.total_value_size);
+    }
+
+    pub fn encode(&self) -> BTreeMap<Vec<u8>, Vec<u8>> {
+        let items = [(PROP_TOTAL_KEY_SIZE, self.total_key_size),
+                     (PROP_TOTAL_VALUE_SIZE, self.total_value_size)];
+        items.iter()
+            .map(|&(k, v)| (k.as_bytes().to_owned(), encode_u64(v)))
+            .collect()
+    }
+
+    pub fn decode<T: DecodeU64>(props: &T) -> Result<SizeProperties, codec::Error> {
+        let mut res = SizeProperties::default();
+        res.total_key_size = try!(props.decode_u64(PROP_TOTAL_KEY_SIZE));
+        res.total_value_size = try!(props.decode_u64(PROP_TOTAL_VALUE_SIZE));
+        Ok(res)
+    }
+}
+
+/// Returns the number of rows per kilobyte, or 0.0 if there are no bytes.
+/// A high density means many tiny rows, which should be split by row count
+/// rather than by size.
+pub fn rows_per_kb(mvcc: &MvccProperties, size: &SizeProperties) -> f64 {
+    let total_size = size.total_size();
+    if total_size == 0 {
+        return 0.0;
+    }
+    mvcc.num_rows as f64 * 1024.0 / total_size as f64
+}
+
+// SSTs written before the version is introduced are treated as version 1.
+fn decode_prop_version<T: DecodeU64>(props: &T) -> Result<u32, codec::Error> {
+    let v = try!(props.decode_u64_or(PROP_VERSION, PROP_VERSION_V1 as u64));
+    if v > u32::MAX as u64 {
+        return Err(codec::Error::InvalidDataType(format!("invalid properties version {}", v)));
+    }
+    // Written by a newer version, which may be encoded differently.
+    if v > CURRENT_PROP_VERSION as u64 {
+        return Err(codec::Error::UnsupportedPropVersion(v as u32));
+    }
+    Ok(v as u32)
+}
+
+// Fields are collected in the aggregate only if all operands collected them,
+// but `FAST_COUNTS_ONLY` skips fields, so it is kept if any operand has it.
+fn merge_flags(a: PropertiesFlags, b: PropertiesFlags) -> PropertiesFlags {
+    let skip = FAST_COUNTS_ONLY.bits();
+    PropertiesFlags::from_bits((a.bits() & b.bits() & !skip) | ((a.bits() | b.bits()) & skip))
+}
+
+// Computes the CRC32 of the MVCC properties in key order. Only keys written by
+// `MvccProperties` are covered, other collectors may add entries to the same SST.
+fn mvcc_checksum<T: DecodeU64>(props: &T) -> u32 {
+    let mut keys: Vec<&str> = PROP_KEYS.iter().map(|k| k.name()).collect();
+    keys.extend_from_slice(&[PROP_VERSION,
+                             PROP_COLLECTED_FLAGS,
+                             PROP_SMALLEST_KEY,
+                             PROP_LARGEST_KEY,
+                             PROP_VERSIONS_HISTOGRAM,
+                             PROP_KEY_HLL,
+                             PROP_KEY_PREFIX_BLOOM,
+                             PROP_KEY_PREFIX_LEN]);
+    keys.sort();
+    let mut digest = Digest::new(crc32::IEEE);
+    for k in keys {
+        if let Some(v) = props.get_raw(k) {
+            Hasher32::write(&mut digest, k.as_bytes());
+            Hasher32::write(&mut digest, v);
+        }
+    }
+    digest.sum32()
+}
+
+// Decodes the user key of `k`, a data key without ts, or returns `None` if it
+// is not encoded.
+fn decode_user_key(k: &[u8]) -> Option<Vec<u8>> {
+    if !keys::validate_data_key(k) {
+        return None;
+    }
+    let mut encoded = keys::origin_key(k);
+    encoded.decode_bytes(false).ok()
+}
+
+// Returns the length of the user key of `k`, or the length of `k` itself if it
+// is not encoded.
+fn user_key_len(k: &[u8]) -> u64 {
+    decode_user_key(k).map_or(k.len(), |raw| raw.len()) as u64
+}
+
+// Returns the first 8 bytes of `key` as a big-endian u64, padded with zeros.
+fn split_window(key: &[u8]) -> u64 {
+    let mut buf = [0; number::U64_SIZE];
+    let len = cmp::min(key.len(), buf.len());
+    buf[..len].copy_from_slice(&key[..len]);
+    (&buf[..]).decode_u64().unwrap()
+}
+
+// Fixed-size integers are big-endian, changing it requires bumping `PROP_VERSION`,
+// or properties written before can't be read correctly.
+fn encode_u64(v: u64) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(8);
+    buf.encode_u64(v).unwrap();
+    buf
+}
+
+fn encode_var_u64(v: u64) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(number::MAX_VAR_U64_LEN);
+    buf.encode_var_u64(v).unwrap();
+    buf
+}
+
+fn encode_compact_bytes(v: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(number::MAX_VAR_I64_LEN + v.len());
+    buf.encode_compact_bytes(v).unwrap();
+    buf
+}
+
+/// The number of buckets in `MvccProperties::versions_histogram`.
+pub const VERSIONS_HISTOGRAM_BUCKETS: usize = 5;
+
+// Rows are bucketed by versions into 1, 2-4, 5-16, 17-64 and 65+.
+fn versions_bucket(versions: u64) -> usize {
+    match versions {
+        0...1 => 0,
+        2...4 => 1,
+        5...16 => 2,
+        17...64 => 3,
+        _ => 4,
+    }
+}
+
+// The HyperLogLog sketch uses 2^10 registers, whose standard error is about 3%.
+const HLL_PRECISION: u32 = 10;
+const HLL_REGISTERS: usize = 1 << HLL_PRECISION;
+
+// The hash is persisted in sketches, so it must be stable across versions.
+fn hash_key(key: &[u8]) -> u64 {
+    let mut hasher = FnvHasher::default();
+    hasher.write(key);
+    // FNV doesn't spread its bits well, so mix them (the splitmix64 finalizer).
+    let mut h = hasher.finish();
+    h = (h ^ (h >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    h = (h ^ (h >> 27)).wrapping_mul(0x94d049bb133111eb);
+    h ^ (h >> 31)
+}
+
+fn hll_insert(registers: &mut [u8], key: &[u8]) {
+    let h = hash_key(key);
+    let idx = (h >> (64 - HLL_PRECISION)) as usize;
+    let rank = cmp::min((h << HLL_PRECISION).leading_zeros(), 64 - HLL_PRECISION) + 1;
+    registers[idx] = cmp::max(registers[idx], rank as u8);
+}
+
+fn hll_merge(registers: &mut [u8], other: &[u8]) {
+    for (r, o) in registers.iter_mut().zip(other) {
+        *r = cmp::max(*r, *o);
+    }
+}
+
+fn hll_estimate(registers: &[u8]) -> f64 {
+    let m = registers.len() as f64;
+    let alpha = 0.7213 / (1.0 + 1.079 / m);
+    let sum: f64 = registers.iter().map(|&r| 2f64.powi(-(r as i32))).sum();
+    let estimate = alpha * m * m / sum;
+    let zeros = registers.iter().filter(|&&r| r == 0).count();
+    // Small cardinalities are estimated by linear counting.
+    if estimate <= 2.5 * m && zeros > 0 {
+        return m * (m / zeros as f64).ln();
+    }
+    estimate
+}
+
+// The bloom filter has 2^14 bits and 4 hash functions, whose false positive
+// rate is about 1% for 1500 distinct prefixes, but grows for more of them.
+const BLOOM_BYTES: usize = 1 << 11;
+const BLOOM_HASHES: u64 = 4;
+
+fn bloom_bits(key: &[u8]) -> Vec<usize> {
+    // Double hashing derives all hash functions from one hash.
+    let h = hash_key(key);
+    let (h1, h2) = (h & 0xffffffff, h >> 32);
+    (0..BLOOM_HASHES)
+        .map(|i| (h1.wrapping_add(i.wrapping_mul(h2)) % (BLOOM_BYTES as u64 * 8)) as usize)
+        .collect()
+}
+
+fn bloom_insert(bloom: &mut [u8], key: &[u8]) {
+    for bit in bloom_bits(key) {
+        bloom[bit / 8] |= 1 << (bit % 8);
+    }
+}
+
+fn bloom_contains(bloom: &[u8], key: &[u8]) -> bool {
+    bloom_bits(key).into_iter().all(|bit| bloom[bit / 8] & (1 << (bit % 8)) != 0)
+}
+
+fn bloom_merge(bloom: &mut [u8], other: &[u8]) {
+    for (b, o) in bloom.iter_mut().zip(other) {
+        *b |= *o;
+    }
+}
+
+fn decode_bytes_or_empty<T: DecodeU64>(props: &T, k: &str) -> Result<Vec<u8>, codec::Error> {
+    match props.decode_bytes(k) {
+        Err(codec::Error::KeyNotFound) => Ok(Vec::new()),
+        res => res,
+    }
+}
+
+pub trait DecodeU64 {
+    /// `get_raw` returns the undecoded value of `k`.
+    fn get_raw(&self, k: &str) -> Option<&[u8]>;
+
+    fn decode_u64(&self, k: &str) -> Result<u64, codec::Error>;
+
+    /// `decode_var_u64` decodes a u64 in the varint form.
+    fn decode_var_u64(&self, k: &str) -> Result<u64, codec::Error>;
+
+    /// `decode_bytes` decodes a length-prefixed byte string.
+    fn decode_bytes(&self, k: &str) -> Result<Vec<u8>, codec::Error>;
+
+    /// `decode_u64_or` returns `default` instead of `KeyNotFound` if `k` is absent.
+    fn decode_u64_or(&self, k: &str, default: u64) -> Result<u64, codec::Error> {
+        match self.decode_u64(k) {
+            Err(codec::Error::KeyNotFound) => Ok(default),
+            res => res,
+        }
+    }
+
+    /// `try_decode_u64` returns `None` instead of `KeyNotFound` if `k` is absent.
+    fn try_decode_u64(&self, k: &str) -> Result<Option<u64>, codec::Error> {
+        match self.decode_u64(k) {
+            Ok(v) => Ok(Some(v)),
+            Err(codec::Error::KeyNotFound) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl DecodeU64 for HashMap<Vec<u8>, Vec<u8>> {
+    fn get_raw(&self, k: &str) -> Option<&[u8]> {
+        self.get(k.as_bytes()).map(|v| v.as_slice())
+    }
+
+    fn decode_u64(&self, k: &str) -> Result<u64, codec::Error> {
+        match self.get(k.as_bytes()) {
+            Some(v) => v.as_slice().decode_u64(),
+            None => Err(codec::Error::KeyNotFound),
+        }
+    }
+
+    fn decode_var_u64(&self, k: &str) -> Result<u64, codec::Error> {
+        match self.get(k.as_bytes()) {
+            Some(v) => v.as_slice().decode_var_u64(),
+            None => Err(codec::Error::KeyNotFound),
+        }
+    }
+
+    fn decode_bytes(&self, k: &str) -> Result<Vec<u8>, codec::Error> {
+        match self.get(k.as_bytes()) {
+            Some(v) => v.as_slice().decode_compact_bytes(),
+            None => Err(codec::Error::KeyNotFound),
+        }
+    }
+}
+
+impl DecodeU64 for BTreeMap<Vec<u8>, Vec<u8>> {
+    fn get_raw(&self, k: &str) -> Option<&[u8]> {
+        self.get(k.as_bytes()).map(|v| v.as_slice())
+    }
+
+    fn decode_u64(&self, k: &str) -> Result<u64, codec::Error> {
+        match self.get(k.as_bytes()) {
+            Some(v) => v.as_slice().decode_u64(),
+            None => Err(codec::Error::KeyNotFound),
+        }
+    }
+
+    fn decode_var_u64(&self, k: &str) -> Result<u64, codec::Error> {
+        match self.get(k.as_bytes()) {
+            Some(v) => v.as_slice().decode_var_u64(),
+            None => Err(codec::Error::KeyNotFound),
+        }
+    }
+
+    fn decode_bytes(&self, k: &str) -> Result<Vec<u8>, codec::Error> {
+        match self.get(k.as_bytes()) {
+            Some(v) => v.as_slice().decode_compact_bytes(),
+            None => Err(codec::Error::KeyNotFound),
+        }
+    }
+}
+
+// Properties decoded from protobuf messages are keyed by `String`.
+impl DecodeU64 for HashMap<String, Vec<u8>> {
+    fn get_raw(&self, k: &str) -> Option<&[u8]> {
+        self.get(k).map(|v| v.as_slice())
+    }
+
+    fn decode_u64(&self, k: &str) -> Result<u64, codec::Error> {
+        match self.get(k) {
+            Some(v) => v.as_slice().decode_u64(),
+            None => Err(codec::Error::KeyNotFound),
+        }
+    }
+
+    fn decode_var_u64(&self, k: &str) -> Result<u64, codec::Error> {
+        match self.get(k) {
+            Some(v) => v.as_slice().decode_var_u64(),
+            None => Err(codec::Error::KeyNotFound),
+        }
+    }
+
+    fn decode_bytes(&self, k: &str) -> Result<Vec<u8>, codec::Error> {
+        match self.get(k) {
+            Some(v) => v.as_slice().decode_compact_bytes(),
+            None => Err(codec::Error::KeyNotFound),
+        }
+    }
+}
+
+impl DecodeU64 for UserCollectedProperties {
+    fn get_raw(&self, k: &str) -> Option<&[u8]> {
+        self.get(k.as_bytes())
+    }
+
+    fn decode_u64(&self, k: &str) -> Result<u64, codec::Error> {
+        match self.get(k.as_bytes()) {
+            Some(mut v) => v.decode_u64(),
+            None => Err(codec::Error::KeyNotFound),
+        }
+    }
+
+    fn decode_var_u64(&self, k: &str) -> Result<u64, codec::Error> {
+        match self.get(k.as_bytes()) {
+            Some(mut v) => v.decode_var_u64(),
+            None => Err(codec::Error::KeyNotFound),
+        }
+    }
+
+    fn decode_bytes(&self, k: &str) -> Result<Vec<u8>, codec::Error> {
+        match self.get(k.as_bytes()) {
+            Some(mut v) => v.decode_compact_bytes(),
+            None => Err(codec::Error::KeyNotFound),
+        }
+    }
+}
+
+/// `SliceProperties` decodes properties from key/value pairs without building
+/// a map, keys are looked up linearly.
+pub struct SliceProperties<'a>(pub &'a [(Vec<u8>, Vec<u8>)]);
+
+impl<'a> SliceProperties<'a> {
+    fn get(&self, k: &str) -> Option<&'a [u8]> {
+        self.0
+            .iter()
+            .find(|&&(ref key, _)| key.as_slice() == k.as_bytes())
+            .map(|&(_, ref v)| v.as_slice())
+    }
+}
+
+impl<'a> DecodeU64 for SliceProperties<'a> {
+    fn get_raw(&self, k: &str) -> Option<&[u8]> {
+        self.get(k)
+    }
+
+    fn decode_u64(&self, k: &str) -> Result<u64, codec::Error> {
+        match self.get(k) {
+            Some(mut v) => v.decode_u64(),
+            None => Err(codec::Error::KeyNotFound),
+        }
+    }
+
+    fn decode_var_u64(&self, k: &str) -> Result<u64, codec::Error> {
+        match self.get(k) {
+            Some(mut v) => v.decode_var_u64(),
+            None => Err(codec::Error::KeyNotFound),
+        }
+    }
+
+    fn decode_bytes(&self, k: &str) -> Result<Vec<u8>, codec::Error> {
+        match self.get(k) {
+            Some(mut v) => v.decode_compact_bytes(),
+            None => Err(codec::Error::KeyNotFound),
+        }
+    }
+}
+
+/// `NamespacedProperties` exposes the properties in `namespace` with the namespace
+/// stripped from their names, e.g. "tikv.custom.a" is looked up as "a". Keys out of
+/// the namespace, e.g. of RocksDB and other collectors, can't be looked up at all.
+pub struct NamespacedProperties<'a, T: 'a> {
+    props: &'a T,
+    namespace: &'a str,
+}
+
+impl<'a, T: DecodeU64> NamespacedProperties<'a, T> {
+    pub fn new(props: &'a T, namespace: &'a str) -> NamespacedProperties<'a, T> {
+        NamespacedProperties {
+            props: props,
+            namespace: namespace,
+        }
+    }
+
+    fn full_key(&self, k: &str) -> String {
+        let mut key = String::with_capacity(self.namespace.len() + k.len());
+        key.push_str(self.namespace);
+        key.push_str(k);
+        key
+    }
+}
+
+impl<'a, T: DecodeU64> DecodeU64 for NamespacedProperties<'a, T> {
+    fn get_raw(&self, k: &str) -> Option<&[u8]> {
+        self.props.get_raw(&self.full_key(k))
+    }
+
+    fn decode_u64(&self, k: &str) -> Result<u64, codec::Error> {
+        self.props.decode_u64(&self.full_key(k))
+    }
+
+    fn decode_var_u64(&self, k: &str) -> Result<u64, codec::Error> {
+        self.props.decode_var_u64(&self.full_key(k))
+    }
+
+    fn decode_bytes(&self, k: &str) -> Result<Vec<u8>, codec::Error> {
+        self.props.decode_bytes(&self.full_key(k))
+    }
+}
+
+// `VarU64Properties` decodes all integers of `T` in the varint form.
+struct VarU64Properties<'a, T: 'a>(&'a T);
+
+impl<'a, T: DecodeU64> DecodeU64 for VarU64Properties<'a, T> {
+    fn get_raw(&self, k: &str) -> Option<&[u8]> {
+        self.0.get_raw(k)
+    }
+
+    fn decode_u64(&self, k: &str) -> Result<u64, codec::Error> {
+        self.0.decode_var_u64(k)
+    }
+
+    fn decode_var_u64(&self, k: &str) -> Result<u64, codec::Error> {
+        self.0.decode_var_u64(k)
+    }
+
+    fn decode_bytes(&self, k: &str) -> Result<Vec<u8>, codec::Error> {
+        self.0.decode_bytes(k)
+    }
+}
+
+/// Rows with more versions than this are counted as hot rows by default.
+pub const DEFAULT_HOT_ROW_VERSIONS: u64 = 128;
+/// The length of user key prefixes in the prefix bloom filter by default.
+pub const DEFAULT_BLOOM_PREFIX_LEN: usize = 8;
+
+/// `CfMode` tells `UserPropertiesCollector` what the entries of the column
+/// family are, and so which properties to collect.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CfMode {
+    // MVCC writes, collects `MvccProperties`.
+    Write,
+    // Locks, collects `LockProperties`.
+    Lock,
+    // MVCC values, collects `DefaultCfProperties`.
+    Default,
+    // Raft logs and states, which have no properties to collect.
+    Raft,
+    // Raw key-values without ts, collects `RawProperties`.
+    Raw,
+}
+
+/// `CollectorOptions` holds all the configurations of `UserPropertiesCollector`.
+#[derive(Clone, Debug)]
+pub struct CollectorOptions {
+    pub cf_mode: CfMode,
+    pub flags: PropertiesFlags,
+    pub hot_row_versions: u64,
+    // The column family name written to properties, empty to not write it.
+    pub cf_name: String,
+    // Versions older than the safe point are counted, 0 to not count them.
+    pub safe_point: u64,
+    // Only 1 in `sample_rate` versions is parsed, 0 or 1 to parse all of them.
+    // Parsing is the most expensive part of collecting, so sampling reduces the
+    // compaction overhead, at the cost of making `num_puts` and `num_deletes`
+    // estimations (scaled up by `sample_rate`), and `num_fully_deleted_rows` and
+    // `num_puts_below_safepoint` lower bounds, since versions not sampled are missed.
+    // `num_pure_tombstone_rows` is also inaccurate, rows whose puts are not sampled
+    // are counted, and rows whose deletes are not sampled are missed.
+    pub sample_rate: u32,
+    // The length of user key prefixes in the bloom filter, with `PREFIX_BLOOM`.
+    pub bloom_prefix_len: usize,
+    // The region id written to properties, so that they can be attributed to
+    // the region, `None` to not write it.
+    pub region_id: Option<u64>,
+    // Only entries whose user keys start with the prefix are collected, others
+    // are ignored as if they were not added. `None` to collect all entries.
+    pub prefix_filter: Option<Vec<u8>>,
+    // Values failing to parse are fatal, `UserProperties::fatal_errors` is set
+    // besides counting them, since collectors can't return errors to RocksDB.
+    pub strict: bool,
+    // Whether keys are added in ascending order, as RocksDB does when writing
+    // SSTs. Rows are told apart by comparing each key with the previous one,
+    // which works either way, but versions of a row are in ascending ts order
+    // if keys are descending, and the first row is the largest one.
+    pub ascending: bool,
+    // The SST is marked suspect if `num_errors` exceeds the ratio of entries,
+    // 1.0 or more to never mark it.
+    pub suspect_error_ratio: f64,
+    // The length of user key prefixes counted in `UserProperties::distinct_prefixes`,
+    // e.g. of table ids, 0 to not count them. Keys are sorted, so the count is
+    // exact, but rows whose keys fail to decode are skipped. It needs `MVCC_PROPERTIES`.
+    pub distinct_prefix_len: usize,
+}
+
+impl CollectorOptions {
+    pub fn from_flags(flags: PropertiesFlags) -> CollectorOptions {
+        CollectorOptions { flags: flags, ..CollectorOptions::default() }
+    }
+}
+
+impl Default for CollectorOptions {
+    fn default() -> CollectorOptions {
+        CollectorOptions {
+            cf_mode: CfMode::Write,
+            flags: MVCC_PROPERTIES,
+            hot_row_versions: DEFAULT_HOT_ROW_VERSIONS,
+            cf_name: String::new(),
+            safe_point: 0,
+            sample_rate: 1,
+            bloom_prefix_len: DEFAULT_BLOOM_PREFIX_LEN,
+            region_id: None,
+            prefix_filter: None,
+            strict: false,
+            ascending: true,
+            suspect_error_ratio: 1.0,
+            distinct_prefix_len: 0,
+        }
+    }
+}
+
+/// `EntryHook` is called with the key, value and type of every valid entry
+/// added to `UserPropertiesCollector`, see `set_entry_hook`.
+pub type EntryHook = Box<FnMut(&[u8], &[u8], DBEntryType) + Send>;
+
+/// `CollectorMetrics` is notified when a collector finishes an SST, so that
+/// collecting can be monitored, e.g. by prometheus counters.
+pub trait CollectorMetrics: Send + Sync {
+    /// Called once for every SST, with the numbers of entries and errors in it.
+    fn on_finish(&self, num_entries: u64, num_errors: u64);
+}
+
+/// `CombineRule` is how values of a custom property are combined, both for the
+/// entries of an SST and for SSTs aggregated together.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CombineRule {
+    Sum,
+    Min,
+    Max,
+}
+
+impl CombineRule {
+    fn initial_value(&self) -> u64 {
+        match *self {
+            CombineRule::Sum | CombineRule::Max => 0,
+            CombineRule::Min => u64::MAX,
+        }
+    }
+
+    fn combine(&self, a: u64, b: u64) -> u64 {
+        match *self {
+            CombineRule::Sum => a.saturating_add(b),
+            CombineRule::Min => cmp::min(a, b),
+            CombineRule::Max => cmp::max(a, b),
+        }
+    }
+}
+
+/// `PropertyCollectFn` returns the value of an entry for a custom property, which
+/// is combined into the value of the SST, see `PropertyRegistry::register`.
+pub type PropertyCollectFn = Box<Fn(&[u8], &[u8], DBEntryType) -> u64 + Send + Sync>;
+
+struct CustomProperty {
+    name: String,
+    rule: CombineRule,
+    collect: PropertyCollectFn,
+}
+
+/// `PropertyRegistry` holds the custom u64 properties collected besides the
+/// built-in ones, so that they can be added without touching `PropKey`.
+/// Install it with `UserPropertiesCollectorFactory::set_registry`, then use
+/// `decode` and `combine` to read the values back and aggregate them.
+#[derive(Default)]
+pub struct PropertyRegistry {
+    props: Vec<CustomProperty>,
+}
+
+impl PropertyRegistry {
+    pub fn new() -> PropertyRegistry {
+        PropertyRegistry::default()
+    }
+
+    /// Registers a property written under `name` in the "tikv.custom." namespace,
+    /// whose value is `collect` of every valid entry combined by `rule`.
+    pub fn register(&mut self,
+                    name: &str,
+                    rule: CombineRule,
+                    collect: PropertyCollectFn)
+                    -> Result<(), String> {
+        if self.props.iter().any(|p| p.name == name) {
+            return Err(format!("property {} is already registered", name));
+        }
+        self.props.push(CustomProperty {
+            name: name.to_owned(),
+            rule: rule,
+            collect: collect,
+        });
+        Ok(())
+    }
+
+    fn initial_values(&self) -> Vec<u64> {
+        self.props.iter().map(|p| p.rule.initial_value()).collect()
+    }
+
+    fn collect(&self, values: &mut [u64], key: &[u8], value: &[u8], entry_type: DBEntryType) {
+        for (p, v) in self.props.iter().zip(values.iter_mut()) {
+            *v = p.rule.combine(*v, (p.collect)(key, value, entry_type));
+        }
+    }
+
+    fn encode(&self, values: &[u64]) -> BTreeMap<Vec<u8>, Vec<u8>> {
+        self.props
+            .iter()
+            .zip(values)
+            .map(|(p, v)| {
+                let key = format!("{}{}", CUSTOM_PROP_NAMESPACE, p.name);
+                (key.into_bytes(), encode_u64(*v))
+            })
+            .collect()
+    }
+
+    /// Decodes the registered properties. Those absent, e.g. in SSTs written
+    /// before they are registered, are left out.
+    pub fn decode<T: DecodeU64>(&self, props: &T) -> Result<BTreeMap<String, u64>, codec::Error> {
+        let props = NamespacedProperties::new(props, CUSTOM_PROP_NAMESPACE);
+        let mut res = BTreeMap::new();
+        for p in &self.props {
+            if let Some(v) = try!(props.try_decode_u64(&p.name)) {
+                res.insert(p.name.clone(), v);
+            }
+        }
+        Ok(res)
+    }
+
+    /// Combines decoded values of another SST into `res` by their rules.
+    pub fn combine(&self, res: &mut BTreeMap<String, u64>, other: &BTreeMap<String, u64>) {
+        for p in &self.props {
+            if let Some(&v) = other.get(&p.name) {
+                let combined = match res.get(&p.name) {
+                    Some(&cur) => p.rule.combine(cur, v),
+                    None => v,
+                };
+                res.insert(p.name.clone(), combined);
+            }
+        }
+    }
+}
+
+pub struct UserPropertiesCollector {
+    opts: CollectorOptions,
+    entry_hook: Option<EntryHook>,
+    metrics: Option<Arc<CollectorMetrics>>,
+    registry: Option<Arc<PropertyRegistry>>,
+    // The values of the custom properties in `registry`, in the same order.
+    custom_values: Vec<u64>,
+    props: UserProperties,
+    last_row: Vec<u8>,
+    // The user key prefix of the last row counted in `distinct_prefixes`.
+    last_prefix: Vec<u8>,
+    row_versions: u64,
+    // The ts of the previous version of the current row.
+    row_last_ts: u64,
+    // The latest ts of the current row.
+    row_latest_ts: u64,
+    // The type and ts of the latest Put or Delete of the current row seen.
+    row_latest_write: Option<(WriteType, u64)>,
+    // Whether the current row has any Put or Delete.
+    row_has_put: bool,
+    row_has_delete: bool,
+}
+
+impl Default for UserPropertiesCollector {
+    fn default() -> UserPropertiesCollector {
+        UserPropertiesCollector::new(CollectorOptions::default())
+    }
+}
+
+impl UserPropertiesCollector {
+    pub fn new(opts: CollectorOptions) -> UserPropertiesCollector {
+        let mut collector = UserPropertiesCollector {
+            opts: opts,
+            entry_hook: None,
+            metrics: None,
+            registry: None,
+            custom_values: Vec::new(),
+            props: UserProperties::new(),
+            last_row: Vec::new(),
+            last_prefix: Vec::new(),
+            row_versions: 0,
+            row_last_ts: 0,
+            row_latest_ts: 0,
+            row_latest_write: None,
+            row_has_put: false,
+            row_has_delete: false,
+        };
+        collector.reset();
+        collector
+    }
+
+    /// Installs a hook to do extra accounting for entries, which is called after
+    /// an entry is validated and before the properties are collected from it.
+    pub fn set_entry_hook(&mut self, hook: EntryHook) {
+        self.entry_hook = Some(hook);
+    }
+
+    /// Reports to `metrics` whenever an SST is finished, see `CollectorMetrics`.
+    pub fn set_metrics(&mut self, metrics: Arc<CollectorMetrics>) {
+        self.metrics = Some(metrics);
+    }
+
+    /// Collects the custom properties in `registry` besides the built-in ones.
+    pub fn set_registry(&mut self, registry: Arc<PropertyRegistry>) {
+        self.custom_values = registry.initial_values();
+        self.registry = Some(registry);
+    }
+
+    /// Clears all the collected properties so that the collector can be reused
+    /// for another SST. The cf id, the entry hook and the allocated buffers are kept.
+    pub fn reset(&mut self) {
+        let mut key_hll = mem::replace(&mut self.props.mvcc.key_hll, Vec::new());
+        let mut prefix_bloom = mem::replace(&mut self.props.mvcc.prefix_bloom, Vec::new());
+        let mut props = UserProperties::new();
+        props.cf_name = self.opts.cf_name.clone();
+        props.cf_id = self.props.cf_id;
+        props.region_id = self.opts.region_id;
+        props.mvcc.collected_flags = Some(self.opts.flags);
+        if self.opts.flags.contains(TIMING) {
+            props.collect_nanos = Some(0);
+        }
+        if self.opts.distinct_prefix_len > 0 {
+            props.distinct_prefixes = Some(0);
+        }
+        match self.opts.cf_mode {
+            CfMode::Write | CfMode::Raft => {}
+            CfMode::Lock => props.lock = Some(LockProperties::new()),
+            CfMode::Default => props.default_cf = Some(DefaultCfProperties::default()),
+            CfMode::Raw => props.raw = Some(RawProperties::default()),
+        }
+        if self.opts.flags.contains(KEY_CARDINALITY) {
+            key_hll.clear();
+            key_hll.resize(HLL_REGISTERS, 0);
+            props.mvcc.key_hll = key_hll;
+        }
+        if self.opts.flags.contains(PREFIX_BLOOM) {
+            prefix_bloom.clear();
+            prefix_bloom.resize(BLOOM_BYTES, 0);
+            props.mvcc.prefix_bloom = prefix_bloom;
+            props.mvcc.bloom_prefix_len = self.opts.bloom_prefix_len as u64;
+        }
+        // Parsing writes is wasted if neither asks for the counters from it,
+        // e.g. if only size or raw properties are wanted.
+        if self.opts.flags.contains(FAST_COUNTS_ONLY) ||
+           !self.opts.flags.intersects(MVCC_PROPERTIES | DELETE_RATIO) {
+            props.mvcc.puts_valid = false;
+        }
+        self.props = props;
+        if let Some(ref registry) = self.registry {
+            self.custom_values = registry.initial_values();
+        }
+        self.last_row.clear();
+        self.last_prefix.clear();
+        self.row_versions = 0;
+        self.row_last_ts = 0;
+        self.row_latest_ts = 0;
+        self.row_latest_write = None;
+        self.row_has_put = false;
+        self.row_has_delete = false;
+    }
+
+    fn collect_default_cf_properties(&mut self, value: &[u8], entry_type: DBEntryType) {
+        match entry_type {
+            DBEntryType::Put => {}
+            _ => return,
+        }
+        let props = self.props.default_cf.as_mut().unwrap();
+        props.total_default_size += value.len() as u64;
+    }
+
+    fn collect_raw_properties(&mut self, key: &[u8], value: &[u8], entry_type: DBEntryType) {
+        match entry_type {
+            DBEntryType::Put => {}
+            _ => return,
+        }
+        let props = self.props.raw.as_mut().unwrap();
+        props.num_keys += 1;
+        props.total_bytes += (key.len() + value.len()) as u64;
+    }
+
+    fn collect_lock_properties(&mut self, value: &[u8], entry_type: DBEntryType) {
+        // Deletes are resolved locks.
+        match entry_type {
+            DBEntryType::Put => {}
+            _ => return,
+        }
+        let lock = match Lock::parse(value) {
+            Ok(lock) => lock,
+            Err(_) => {
+                self.props.num_errors += 1;
+                self.props.fatal_errors |= self.opts.strict;
+                return;
+            }
+        };
+        let props = self.props.lock.as_mut().unwrap();
+        props.num_locks += 1;
+        props.min_ts = cmp::min(props.min_ts, lock.ts);
+        props.max_ts = cmp::max(props.max_ts, lock.ts);
+    }
+
+    // `k` is the row key without ts, which is split from the key only once in `add`.
+    fn collect_mvcc_properties(&mut self,
+                               k: &[u8],
+                               ts: u64,
+                               value: &[u8],
+                               entry_type: DBEntryType) {
+        let full = self.opts.flags.contains(MVCC_PROPERTIES);
+        let mvcc = &mut self.props.mvcc;
+        if full {
+            mvcc.min_ts = cmp::min(mvcc.min_ts, ts);
+            mvcc.max_ts = cmp::max(mvcc.max_ts, ts);
+        }
+        match entry_type {
+            DBEntryType::Put => mvcc.num_versions += 1,
+            DBEntryType::Merge => {
+                mvcc.num_merges += 1;
+                return;
+            }
+            // Tombstones of versions, e.g. deleted by GC.
+            DBEntryType::Delete | DBEntryType::SingleDelete => return,
+            DBEntryType::Other => {
+                if self.opts.flags.contains(STRICT_ENTRY_TYPES) {
+                    self.props.num_errors += 1;
+                }
+                return;
+            }
+        }
+        if ts < self.opts.safe_point {
+            mvcc.versions_below_safepoint += 1;
+        }
+        // The first byte is the write type, which is cheap to check without parsing.
+        if !mvcc.deletes_seen && !value.is_empty() &&
+           WriteType::from_u8(value[0]) == Some(WriteType::Delete) {
+            mvcc.deletes_seen = true;
+        }
+
+        if full {
+            // Only the row key (without ts) is kept in `last_row`, and it is
+            // copied once per row into the reused buffer, not once per version.
+            if k != self.last_row.as_slice() {
+                if mvcc.num_rows == 0 {
+                    if self.opts.ascending {
+                        mvcc.smallest_key = k.to_vec();
+                    } else {
+                        mvcc.largest_key = k.to_vec();
+                    }
+                }
+                if mvcc.num_rows == 1 {
+                    mvcc.first_row_versions = self.row_versions;
+                }
+                if mvcc.num_rows > 0 {
+                    mvcc.versions_histogram[versions_bucket(self.row_versions)] += 1;
+                    mvcc.min_row_latest_ts = cmp::min(mvcc.min_row_latest_ts,
+                                                      self.row_latest_ts);
+                    if self.row_has_delete && !self.row_has_put {
+                        mvcc.num_pure_tombstone_rows += 1;
+                    }
+                    if self.row_versions == 1 {
+                        mvcc.num_single_version_rows += 1;
+                    }
+                    match self.row_latest_write {
+                        Some((WriteType::Delete, _)) => mvcc.num_fully_deleted_rows += 1,
+                        // It's counted, but isn't shadowed by a newer version.
+                        Some((WriteType::Put, ts)) if ts < self.opts.safe_point => {
+                            mvcc.num_puts_below_safepoint -= 1
+                        }
+                        _ => {}
+                    }
+                }
+                mvcc.num_rows += 1;
+                // The user key is decoded only once per row, for all the properties
+                // computed from it, as decoding allocates.
+                let raw = decode_user_key(k);
+                mvcc.total_row_key_len += raw.as_ref().map_or(k.len(), |raw| raw.len()) as u64;
+                if !mvcc.key_hll.is_empty() {
+                    hll_insert(&mut mvcc.key_hll, k);
+                }
+                if !mvcc.prefix_bloom.is_empty() {
+                    match raw {
+                        Some(ref raw) => {
+                            let len = cmp::min(raw.len(), self.opts.bloom_prefix_len);
+                            bloom_insert(&mut mvcc.prefix_bloom, &raw[..len]);
+                        }
+                        // The filter can't tell whether the key exists any more.
+                        None => mvcc.prefix_bloom.clear(),
+                    }
+                }
+                if let Some(ref mut n) = self.props.distinct_prefixes {
+                    if let Some(ref raw) = raw {
+                        // Rows are sorted, so rows with the same prefix are adjacent.
+                        let prefix = &raw[..cmp::min(raw.len(), self.opts.distinct_prefix_len)];
+                        if *n == 0 || prefix != self.last_prefix.as_slice() {
+                            *n += 1;
+                            self.last_prefix.clear();
+                            self.last_prefix.extend_from_slice(prefix);
+                        }
+                    }
+                }
+                self.row_versions = 1;
+                self.row_latest_ts = ts;
+                self.row_latest_write = None;
+                self.row_has_put = false;
+                self.row_has_delete = false;
+                self.last_row.clear();
+                self.last_row.extend_from_slice(k);
+            } else {
+                self.row_versions += 1;
+                // Versions of a row are sorted by ts in descending order (ascending
+                // if keys are descending), otherwise the keys are corrupted.
+                let (newer, older) = if self.opts.ascending {
+                    (self.row_last_ts, ts)
+                } else {
+                    (ts, self.row_last_ts)
+                };
+                if older > newer {
+                    mvcc.num_ts_anomalies += 1;
+                } else if older == newer {
+                    mvcc.num_duplicate_versions += 1;
+                } else {
+                    mvcc.max_version_gap = cmp::max(mvcc.max_version_gap, newer - older);
+                }
+                self.row_latest_ts = cmp::max(self.row_latest_ts, ts);
+            }
+            self.row_last_ts = ts;
+            if self.row_versions > mvcc.max_row_versions {
+                mvcc.max_row_versions = self.row_versions;
+            }
+            // Only counts a row once, when it just exceeds the threshold.
+            if self.row_versions - 1 == self.opts.hot_row_versions {
+                mvcc.num_hot_rows += 1;
+            }
+        }
+
+        if !mvcc.puts_valid {
+            return;
+        }
+        let rate = self.opts.sample_rate as u64;
+        if rate > 1 && (mvcc.num_versions - 1) % rate != 0 {
+            return;
+        }
+
+        // `Write::parse` returns an error instead of panicking on corrupted
+        // values, so they never abort the SST write.
+        let v = match Write::parse(value) {
+            Ok(v) => v,
+            Err(_) => {
+                self.props.num_errors += 1;
+                self.props.fatal_errors |= self.opts.strict;
+                return;
+            }
+        };
+
+        mvcc.min_start_ts = cmp::min(mvcc.min_start_ts, v.start_ts);
+        mvcc.max_start_ts = cmp::max(mvcc.max_start_ts, v.start_ts);
+        match v.write_type {
+            WriteType::Put => {
+                mvcc.num_puts += 1;
+                if v.short_value.is_some() {
+                    mvcc.num_inlined_values += 1;
+                } else {
+                    mvcc.num_pointer_values += 1;
+                }
+            }
+            WriteType::Delete => mvcc.num_deletes += 1,
+            WriteType::Rollback => {
+                mvcc.num_rollbacks += 1;
+                mvcc.num_non_data_versions += 1;
+            }
+            WriteType::Lock => mvcc.num_non_data_versions += 1,
+        }
+
+        // Versions of a row are sorted by ts in descending order if keys are
+        // ascending, so the first Put or Delete is the latest one, otherwise
+        // the last one is. Lock and Rollback are not data versions, so skip them.
+        // Puts below the safe point are counted as shadowed, and uncounted when
+        // the row ends if it turns out to be the latest one.
+        if full && (v.write_type == WriteType::Put || v.write_type == WriteType::Delete) {
+            if v.write_type == WriteType::Put {
+                self.row_has_put = true;
+                if ts < self.opts.safe_point {
+                    mvcc.num_puts_below_safepoint += 1;
+                }
+            } else {
+                self.row_has_delete = true;
+            }
+            if self.row_latest_write.is_none() || !self.opts.ascending {
+                self.row_latest_write = Some((v.write_type, ts));
+            }
+        }
+    }
+
+    fn match_prefix_filter(&self, key: &[u8]) -> bool {
+        let prefix = match self.opts.prefix_filter {
+            Some(ref prefix) => prefix,
+            None => return true,
+        };
+        if !keys::validate_data_key(key) {
+            return false;
+        }
+        let mut encoded = keys::origin_key(key);
+        if self.opts.cf_mode == CfMode::Raw {
+            return encoded.starts_with(prefix);
+        }
+        match encoded.decode_bytes(false) {
+            Ok(k) => k.starts_with(prefix),
+            Err(_) => false,
+        }
+    }
+
+    fn add_entry(&mut self, key: &[u8], value: &[u8], entry_type: DBEntryType) {
+        if key.is_empty() {
+            self.props.num_errors += 1;
+            return;
+        }
+        if !keys::validate_data_key(key) {
+            self.props.num_non_data_keys += 1;
+            return;
+        }
+        // Only deletes carry empty values, a put always has a write, lock or value,
+        // except for raw key-values.
+        if value.is_empty() && self.opts.cf_mode != CfMode::Raw {
+            if let DBEntryType::Put = entry_type {
+                self.props.num_errors += 1;
+                return;
+            }
+        }
+        if let Some(ref mut hook) = self.entry_hook {
+            hook(key, value, entry_type);
+        }
+        if let Some(ref registry) = self.registry {
+            registry.collect(&mut self.custom_values, key, value, entry_type);
+        }
+        if self.props.first_key.is_none() && self.opts.flags.contains(FIRST_KEY) {
+            self.props.first_key = Some(key.to_vec());
+        }
+        if let DBEntryType::Put = entry_type {
+            let mvcc = &mut self.props.mvcc;
+            mvcc.max_value_size = cmp::max(mvcc.max_value_size, value.len() as u64);
+        }
+
+        match self.opts.cf_mode {
+            CfMode::Write => {}
+            // Lock keys have no ts.
+            CfMode::Lock => return self.collect_lock_properties(value, entry_type),
+            CfMode::Default => return self.collect_default_cf_properties(value, entry_type),
+            CfMode::Raft => return,
+            CfMode::Raw => return self.collect_raw_properties(key, value, entry_type),
+        }
+
+        let (k, ts) = match types::split_encoded_key_on_ts(key) {
+            Ok((k, ts)) => (k, ts),
+            Err(_) => {
+                self.props.num_errors += 1;
+                return;
+            }
+        };
+        self.collect_mvcc_properties(k, ts, value, entry_type);
+    }
+}
+
+impl TablePropertiesCollector for UserPropertiesCollector {
+    fn add(&mut self, key: &[u8], value: &[u8], entry_type: DBEntryType, _: u64, _: u64) {
+        if !self.match_prefix_filter(key) {
+            return;
+        }
+        self.props.entry_count += 1;
+        {
+            let mvcc = &mut self.props.mvcc;
+            match entry_type {
+                DBEntryType::Put => mvcc.entry_type_puts += 1,
+                DBEntryType::Delete => mvcc.entry_type_deletes += 1,
+                _ => mvcc.entry_type_other += 1,
+            }
+        }
+        // Reading the clock is not free, so only do it when asked to.
+        if self.props.collect_nanos.is_none() {
+            return self.add_entry(key, value, entry_type);
+        }
+        let t = Instant::now();
+        self.add_entry(key, value, entry_type);
+        let nanos = duration_to_nanos(t.elapsed());
+        if let Some(ref mut total) = self.props.collect_nanos {
+            *total += nanos;
+        }
+    }
+
+    fn finish(&mut self) -> HashMap<Vec<u8>, Vec<u8>> {
+        {
+            // Keys are sorted, so the last row is the largest one, or the
+            // smallest one if keys are descending.
+            let mvcc = &mut self.props.mvcc;
+            if self.opts.ascending {
+                mvcc.largest_key = self.last_row.clone();
+            } else {
+                mvcc.smallest_key = self.last_row.clone();
+            }
+            if mvcc.num_rows == 1 {
+                mvcc.first_row_versions = self.row_versions;
+            }
+            if mvcc.num_rows > 0 {
+                mvcc.versions_histogram[versions_bucket(self.row_versions)] += 1;
+                mvcc.min_row_latest_ts = cmp::min(mvcc.min_row_latest_ts, self.row_latest_ts);
+                if self.row_has_delete && !self.row_has_put {
+                    mvcc.num_pure_tombstone_rows += 1;
+                }
+                if self.row_versions == 1 {
+                    mvcc.num_single_version_rows += 1;
+                }
+                match self.row_latest_write {
+                    Some((WriteType::Delete, _)) => mvcc.num_fully_deleted_rows += 1,
+                    Some((WriteType::Put, ts)) if ts < self.opts.safe_point => {
+                        mvcc.num_puts_below_safepoint -= 1
+                    }
+                    _ => {}
+                }
+            }
+            mvcc.last_row_versions = self.row_versions;
+            if !self.opts.ascending {
+                mem::swap(&mut mvcc.first_row_versions, &mut mvcc.last_row_versions);
+            }
+            if self.opts.sample_rate > 1 {
+                let rate = self.opts.sample_rate as u64;
+                mvcc.num_puts = cmp::min(mvcc.num_puts.saturating_mul(rate), mvcc.num_versions);
+                mvcc.num_deletes = cmp::min(mvcc.num_deletes.saturating_mul(rate),
+                                            mvcc.num_versions - mvcc.num_puts);
+                mvcc.num_rollbacks = cmp::min(mvcc.num_rollbacks.saturating_mul(rate),
+                                              mvcc.num_versions - mvcc.num_puts -
+                                              mvcc.num_deletes);
+                let non_data = mvcc.num_non_data_versions.saturating_mul(rate);
+                let max_non_data = mvcc.num_versions - mvcc.num_puts - mvcc.num_deletes;
+                mvcc.num_non_data_versions = cmp::min(non_data, max_non_data);
+                let inlined = mvcc.num_inlined_values.saturating_mul(rate);
+                mvcc.num_inlined_values = cmp::min(inlined, mvcc.num_puts);
+                mvcc.num_pointer_values = mvcc.num_puts - mvcc.num_inlined_values;
+            }
+        }
+        let max_errors = self.props.entry_count as f64 * self.opts.suspect_error_ratio;
+        self.props.suspect = self.props.num_errors as f64 > max_errors;
+        if let Some(ref metrics) = self.metrics {
+            metrics.on_finish(self.props.entry_count, self.props.num_errors);
+        }
+        let mut res = self.props.encode();
+        if let Some(ref registry) = self.registry {
+            res.extend(registry.encode(&self.custom_values));
+        }
+        res.into_iter().collect()
+    }
+}
+
+#[derive(Default)]
+pub struct UserPropertiesCollectorFactory {
+    opts: CollectorOptions,
+    metrics: Option<Arc<CollectorMetrics>>,
+    registry: Option<Arc<PropertyRegistry>>,
+}
+
+impl UserPropertiesCollectorFactory {
+    pub fn new(opts: CollectorOptions) -> UserPropertiesCollectorFactory {
+        UserPropertiesCollectorFactory {
+            opts: opts,
+            metrics: None,
+            registry: None,
+        }
+    }
+
+    /// Makes all the collectors created report to `metrics`, which is shared by them.
+    pub fn set_metrics(&mut self, metrics: Arc<CollectorMetrics>) {
+        self.metrics = Some(metrics);
+    }
+
+    /// Makes all the collectors created collect the custom properties in `registry`.
+    pub fn set_registry(&mut self, registry: Arc<PropertyRegistry>) {
+        self.registry = Some(registry);
+    }
+
+    pub fn with_flags(flags: PropertiesFlags) -> UserPropertiesCollectorFactory {
+        UserPropertiesCollectorFactory::new(CollectorOptions::from_flags(flags))
+    }
+}
+
+impl TablePropertiesCollectorFactory for UserPropertiesCollectorFactory {
+    fn create_table_properties_collector(&mut self, cf: u32) -> Box<TablePropertiesCollector> {
+        // Don't waste time on the entries if nothing is collected.
+        if self.opts.cf_mode == CfMode::Raft {
+            return Box::new(NoopCollector::default());
+        }
+        let mut collector = UserPropertiesCollector::new(self.opts.clone());
+        collector.props.cf_id = Some(cf);
+        collector.metrics = self.metrics.clone();
+        if let Some(ref registry) = self.registry {
+            collector.set_registry(registry.clone());
+        }
+        Box::new(collector)
+    }
+}
+
+/// `NoopCollector` ignores all the entries and collects no properties.
+#[derive(Default)]
+pub struct NoopCollector {}
+
+impl TablePropertiesCollector for NoopCollector {
+    fn add(&mut self, _: &[u8], _: &[u8], _: DBEntryType, _: u64, _: u64) {}
+
+    fn finish(&mut self) -> HashMap<Vec<u8>, Vec<u8>> {
+        HashMap::new()
+    }
+}
+
+#[derive(Default)]
+pub struct SizePropertiesCollector {
+    props: SizeProperties,
+}
+
+impl TablePropertiesCollector for SizePropertiesCollector {
+    fn add(&mut self, key: &[u8], value: &[u8], _: DBEntryType, _: u64, _: u64) {
+        self.props.total_key_size += key.len() as u64;
+        self.props.total_value_size += value.len() as u64;
+    }
+
+    fn finish(&mut self) -> HashMap<Vec<u8>, Vec<u8>> {
+        self.props.encode().into_iter().collect()
+    }
+}
+
+#[derive(Default)]
+pub struct SizePropertiesCollectorFactory {}
+
+impl TablePropertiesCollectorFactory for SizePropertiesCollectorFactory {
+    fn create_table_properties_collector(&mut self, _: u32) -> Box<TablePropertiesCollector> {
+        Box::new(SizePropertiesCollector::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{i64, u64, usize};
+    use std::collections::{BTreeMap, HashMap};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use rocksdb::{DBEntryType, TablePropertiesCollector, TablePropertiesCollectorFactory};
+    use storage::Key;
+    use storage::mvcc::{Write, WriteType, Lock, LockType};
+    use raftstore::store::keys;
+    use rand::{self, Rng};
+    use futures::{future, Future};
+    use util::codec;
+    use util::codec::number::NumberEncoder;
+    use super::{MvccProperties, UserProperties, UserPropertiesCollector, SizeProperties,
+                SizePropertiesCollector, GetPropertiesOptions, DecodeU64, filter_properties,
+                CollectorOptions, UserPropertiesCollectorFactory, DELETE_RATIO, rows_per_kb,
+                LockProperties, CfMode, MVCC_PROPERTIES, STRICT_ENTRY_TYPES,
+                KEY_CARDINALITY, MvccPropertiesAggregator, FAST_COUNTS_ONLY, PropertiesFlags,
+                PREFIX_BLOOM, MvccPropertiesDiff, SliceProperties, PropKey, TIMING,
+                decode_and_merge, aggregate_properties, FIRST_KEY, CollectorMetrics,
+                oldest_ts, PropertyRegistry, CombineRule, NamespacedProperties};
+
+    #[test]
+    fn test_mvcc_properties() {
+        let cases = [("ab", 2, WriteType::Put, DBEntryType::Put),
+                     ("ab", 1, WriteType::Delete, DBEntryType::Put),
+                     ("ab", 1, WriteType::Delete, DBEntryType::Delete),
+                     ("cd", 5, WriteType::Delete, DBEntryType::Put),
+                     ("cd", 4, WriteType::Put, DBEntryType::Put),
+                     ("cd", 3, WriteType::Put, DBEntryType::Put),
+                     ("ef", 6, WriteType::Put, DBEntryType::Put),
+                     ("ef", 6, WriteType::Put, DBEntryType::Delete),
+                     ("gh", 7, WriteType::Delete, DBEntryType::Put)];
+        let mut collector = UserPropertiesCollector::default();
+        for &(key, ts, write_type, entry_type) in &cases {
+            let k = Key::from_raw(key.as_bytes()).append_ts(ts);
+            let k = keys::data_key(k.encoded());
+            let v = Write::new(write_type, ts, None).to_bytes();
+            collector.add(&k, &v, entry_type, 0, 0);
+        }
+        collector.add(b"error", b"error", DBEntryType::Put, 0, 0);
+
+        let props = collector.finish();
+        assert_eq!(UserProperties::decode_num_errors(&props).unwrap(), 0);
+        assert_eq!(UserProperties::decode(&props).unwrap().num_non_data_keys, 1);
+        let props = MvccProperties::decode(&props).unwrap();
+        assert_eq!(props.min_ts, 1);
+        assert_eq!(props.max_ts, 7);
         assert_eq!(props.num_rows, 4);
         assert_eq!(props.num_puts, 4);
+        assert_eq!(props.num_deletes, 3);
         assert_eq!(props.num_versions, 7);
         assert_eq!(props.max_row_versions, 3);
+        let smallest = keys::data_key(Key::from_raw(b"ab").encoded());
+        let largest = keys::data_key(Key::from_raw(b"gh").encoded());
+        assert_eq!(props.smallest_key, smallest);
+        assert_eq!(props.largest_key, largest);
+    }
+
+    #[test]
+    fn test_key_range_properties() {
+        let mut props = MvccProperties::new();
+        props.num_versions = 1;
+        props.smallest_key = b"b".to_vec();
+        props.largest_key = b"c".to_vec();
+        let encoded = props.encode();
+        let decoded = MvccProperties::decode(&encoded).unwrap();
+        assert_eq!(decoded.smallest_key, b"b".to_vec());
+        assert_eq!(decoded.largest_key, b"c".to_vec());
+
+        let mut other = props.clone();
+        other.smallest_key = b"a".to_vec();
+        other.largest_key = b"b".to_vec();
+        props.add(&other);
+        assert_eq!(props.smallest_key, b"a".to_vec());
+        assert_eq!(props.largest_key, b"c".to_vec());
+
+        // Older SSTs have no key range.
+        let mut encoded = props.encode_with_version(super::PROP_VERSION_V2);
+        encoded.remove(super::PROP_SMALLEST_KEY.as_bytes());
+        encoded.remove(super::PROP_LARGEST_KEY.as_bytes());
+        let decoded = MvccProperties::decode(&encoded).unwrap();
+        assert!(decoded.smallest_key.is_empty());
+        assert!(decoded.largest_key.is_empty());
+    }
+
+    #[test]
+    fn test_decode_u64_or() {
+        let props = MvccProperties::new().encode_with_version(super::PROP_VERSION_V1);
+        assert_eq!(props.decode_u64_or(PropKey::NumRows.name(), 7).unwrap(), 0);
+        assert_eq!(props.decode_u64_or("tikv.not_exist", 7).unwrap(), 7);
+
+        let mut props = props;
+        props.insert(b"tikv.bad".to_vec(), b"bad".to_vec());
+        assert!(props.decode_u64_or("tikv.bad", 7).is_err());
+    }
+
+    #[test]
+    fn test_decode_without_baseline_keys() {
+        for k in &[PropKey::MinTs, PropKey::MaxTs, PropKey::NumRows] {
+            let mut props = MvccProperties::new().encode();
+            props.remove(k.as_bytes());
+            assert!(MvccProperties::decode(&props).is_err());
+        }
+    }
+
+    #[test]
+    fn test_prop_version() {
+        let mut props = MvccProperties::new();
+        props.num_rows = 1;
+        let encoded = props.encode();
+        assert_eq!(encoded.decode_u64(super::PROP_VERSION).unwrap(),
+                   super::CURRENT_PROP_VERSION as u64);
+        assert_eq!(MvccProperties::decode(&encoded).unwrap().num_rows, 1);
+
+        // Properties without a version are decoded as version 1.
+        let mut encoded = props.encode_with_version(super::PROP_VERSION_V1);
+        assert_eq!(MvccProperties::decode(&encoded).unwrap().num_rows, 1);
+        encoded.remove(super::PROP_VERSION.as_bytes());
+        assert_eq!(MvccProperties::decode(&encoded).unwrap().num_rows, 1);
+
+        for &v in &[0, super::CURRENT_PROP_VERSION as u64 + 1, u64::MAX] {
+            encoded.insert(super::PROP_VERSION.as_bytes().to_owned(), super::encode_u64(v));
+            assert!(MvccProperties::decode(&encoded).is_err());
+        }
+
+        // Properties from the future are rejected rather than misinterpreted.
+        let future = super::CURRENT_PROP_VERSION + 1;
+        let mut encoded = props.encode();
+        encoded.insert(super::PROP_VERSION.as_bytes().to_owned(),
+                       super::encode_u64(future as u64));
+        match MvccProperties::decode(&encoded) {
+            Err(codec::Error::UnsupportedPropVersion(v)) => assert_eq!(v, future),
+            res => panic!("expect unsupported version error, got {:?}", res),
+        }
+        match MvccProperties::decode_field(&encoded, PropKey::NumRows) {
+            Err(codec::Error::UnsupportedPropVersion(v)) => assert_eq!(v, future),
+            res => panic!("expect unsupported version error, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_decode_without_num_deletes() {
+        let mut props = MvccProperties::new();
+        props.num_rows = 1;
+        props.num_deletes = 1;
+        let mut encoded = props.encode_with_version(super::PROP_VERSION_V2);
+        encoded.remove(PropKey::NumDeletes.as_bytes());
+        let decoded = MvccProperties::decode(&encoded).unwrap();
+        assert_eq!(decoded.num_rows, 1);
+        assert_eq!(decoded.num_deletes, 0);
+    }
+
+    #[test]
+    fn test_size_properties() {
+        let cases = [("a", "1"), ("bb", "22"), ("ccc", "")];
+        let mut collector = SizePropertiesCollector::default();
+        for &(k, v) in &cases {
+            collector.add(k.as_bytes(), v.as_bytes(), DBEntryType::Put, 0, 0);
+        }
+        let mut props = SizeProperties::decode(&collector.finish()).unwrap();
+        assert_eq!(props.total_key_size, 6);
+        assert_eq!(props.total_value_size, 3);
+
+        let other = props.clone();
+        props.add(&other);
+        assert_eq!(props.total_key_size, 12);
+        assert_eq!(props.total_value_size, 6);
+        assert_eq!(props.total_size(), 18);
+
+        // Sizes saturate instead of overflowing.
+        let huge = SizeProperties {
+            total_key_size: u64::MAX - 1,
+            total_value_size: u64::MAX - 1,
+        };
+        assert_eq!(huge.total_size(), u64::MAX);
+        props.add(&huge);
+        assert_eq!(props.total_key_size, u64::MAX);
+        assert_eq!(props.total_value_size, u64::MAX);
+    }
+
+    #[test]
+    fn test_filter_properties() {
+        let props = vec![MvccProperties::with(1, 20, 1, 1, 1, 1),
+                         MvccProperties::with(5, 20, 1, 1, 1, 1),
+                         MvccProperties::with(10, 20, 1, 1, 1, 1)];
+
+        let mut opts = GetPropertiesOptions::default();
+        assert_eq!(filter_properties(&props, &opts).len(), 3);
+
+        // `min_ts` equals `max_ts`.
+        opts.max_ts = Some(5);
+        let res = filter_properties(&props, &opts);
+        assert_eq!(res.len(), 2);
+        assert_eq!(res[0].min_ts, 1);
+        assert_eq!(res[1].min_ts, 5);
+        assert!(props[1].overlaps_ts(5));
+        assert!(!props[1].overlaps_ts(4));
+
+        opts.max_ts = Some(0);
+        assert!(filter_properties(&props, &opts).is_empty());
+
+        // Empty properties have no version, whatever `max_ts` is.
+        let empty = MvccProperties::new();
+        assert!(!empty.overlaps_ts(0));
+        assert!(!empty.overlaps_ts(u64::MAX));
+        let mut empty = empty;
+        empty.collected_flags = Some(DELETE_RATIO);
+        assert!(!empty.overlaps_ts(u64::MAX));
+        opts.max_ts = None;
+        assert!(filter_properties(&[MvccProperties::new()], &opts).is_empty());
+    }
+
+    #[test]
+    fn test_oldest_ts() {
+        let empty: Vec<MvccProperties> = vec![];
+        assert_eq!(oldest_ts(&empty), u64::MAX);
+
+        // Empty properties, whatever their `min_ts` is, are skipped.
+        let mut props = vec![MvccProperties::new(), MvccProperties::default()];
+        assert_eq!(props[1].min_ts, 0);
+        assert_eq!(oldest_ts(&props), u64::MAX);
+
+        props.push(MvccProperties::with(7, 9, 1, 1, 2, 2));
+        props.push(MvccProperties::new());
+        props.push(MvccProperties::with(5, 20, 1, 1, 1, 1));
+        props.push(MvccProperties::with(10, 10, 1, 1, 1, 1));
+        assert_eq!(oldest_ts(&props), 5);
+        assert_eq!(oldest_ts(props.iter().skip(5)), 10);
+    }
+
+    #[test]
+    fn test_mvcc_properties_from_iter() {
+        let mut props = vec![MvccProperties::new(); 2];
+        props[0].min_ts = 3;
+        props[0].max_ts = 5;
+        props[0].num_rows = 1;
+        props[0].num_versions = 2;
+        props[0].max_row_versions = 2;
+        props[1].min_ts = 2;
+        props[1].max_ts = 4;
+        props[1].num_rows = 2;
+        props[1].num_versions = 2;
+        props[1].max_row_versions = 1;
+
+        let res = MvccProperties::from_iter(props.clone());
+        assert_eq!(res.min_ts, 2);
+        assert_eq!(res.max_ts, 5);
+        assert_eq!(res.num_rows, 3);
+        assert_eq!(res.max_row_versions, 2);
+
+        let res: MvccProperties = props.into_iter().collect();
+        assert_eq!(res.min_ts, 2);
+        assert_eq!(res.num_rows, 3);
+
+        let res: MvccProperties = Vec::new().into_iter().collect();
+        assert_eq!(res.min_ts, u64::MAX);
+        assert_eq!(res.max_ts, 0);
+    }
+
+    #[test]
+    fn test_add_empty_mvcc_properties() {
+        // A table with tombstones only has timestamps but no versions.
+        let mut collector = UserPropertiesCollector::default();
+        let k = Key::from_raw(b"k").append_ts(1);
+        collector.add(&keys::data_key(k.encoded()), b"", DBEntryType::Delete, 0, 0);
+        let empty = MvccProperties::decode(&collector.finish()).unwrap();
+        assert!(empty.is_empty());
+        assert_eq!(empty.min_ts, 1);
+
+        let mut props = MvccProperties::new();
+        props.min_ts = 5;
+        props.max_ts = 10;
+        props.num_rows = 1;
+        props.num_versions = 1;
+        props.add(&empty);
+        props.add(&MvccProperties::new());
+        assert_eq!(props.min_ts, 5);
+        assert_eq!(props.max_ts, 10);
+        assert_eq!(props.num_versions, 1);
+    }
+
+    #[test]
+    fn test_split_suggestion() {
+        let cases = [(0, 10, false, 1),
+                     (9, 10, false, 1),
+                     (10, 10, false, 1),
+                     (11, 10, true, 2),
+                     (20, 10, true, 2),
+                     (21, 10, true, 3),
+                     (100, 0, false, 1)];
+        for &(num_rows, max_rows, should_split, regions) in &cases {
+            let mut props = MvccProperties::new();
+            props.num_rows = num_rows;
+            assert_eq!(props.should_split(max_rows), should_split);
+            assert_eq!(props.estimated_regions(max_rows), regions);
+        }
+    }
+
+    #[test]
+    fn test_delete_ratio() {
+        let cases = [("ab", 3, WriteType::Delete),
+                     ("ab", 2, WriteType::Put),
+                     ("cd", 5, WriteType::Put),
+                     ("cd", 4, WriteType::Delete)];
+        let opts = CollectorOptions::from_flags(DELETE_RATIO);
+        let mut collector = UserPropertiesCollector::new(opts);
+        for &(key, ts, write_type) in &cases {
+            let k = Key::from_raw(key.as_bytes()).append_ts(ts);
+            let k = keys::data_key(k.encoded());
+            let v = Write::new(write_type, ts, None).to_bytes();
+            collector.add(&k, &v, DBEntryType::Put, 0, 0);
+        }
+        let props = MvccProperties::decode(&collector.finish()).unwrap();
+        assert_eq!(props.num_versions, 4);
+        assert_eq!(props.num_deletes, 2);
+        assert_eq!(props.delete_ratio(), 0.5);
+        // Other properties are not collected.
+        assert_eq!(props.num_rows, 0);
+        assert_eq!(props.max_row_versions, 0);
+
+        assert_eq!(MvccProperties::new().delete_ratio(), 0.0);
+    }
+
+    #[test]
+    fn test_num_hot_rows() {
+        // (key, versions)
+        let cases = [("a", 1), ("b", 2), ("c", 3), ("d", 5)];
+        let mut opts = CollectorOptions::default();
+        opts.hot_row_versions = 2;
+        let mut collector = UserPropertiesCollector::new(opts);
+        for &(key, versions) in &cases {
+            for ts in (0..versions).rev() {
+                let k = Key::from_raw(key.as_bytes()).append_ts(ts);
+                let k = keys::data_key(k.encoded());
+                let v = Write::new(WriteType::Put, ts, None).to_bytes();
+                collector.add(&k, &v, DBEntryType::Put, 0, 0);
+            }
+        }
+        let mut props = MvccProperties::decode(&collector.finish()).unwrap();
+        assert_eq!(props.num_rows, 4);
+        assert_eq!(props.num_hot_rows, 2);
+
+        let other = props.clone();
+        props.add(&other);
+        assert_eq!(props.num_hot_rows, 4);
+    }
+
+    #[test]
+    fn test_collector_factory_options() {
+        let k = Key::from_raw(b"k").append_ts(1);
+        let k = keys::data_key(k.encoded());
+        let v = Write::new(WriteType::Delete, 1, None).to_bytes();
+
+        let mut factory = UserPropertiesCollectorFactory::with_flags(DELETE_RATIO);
+        let mut collector = factory.create_table_properties_collector(0);
+        collector.add(&k, &v, DBEntryType::Put, 0, 0);
+        let props = MvccProperties::decode(&collector.finish()).unwrap();
+        assert_eq!(props.num_deletes, 1);
+        assert_eq!(props.num_rows, 0);
+
+        let mut opts = CollectorOptions::default();
+        opts.hot_row_versions = 0;
+        let mut factory = UserPropertiesCollectorFactory::new(opts);
+        let mut collector = factory.create_table_properties_collector(0);
+        collector.add(&k, &v, DBEntryType::Put, 0, 0);
+        let props = MvccProperties::decode(&collector.finish()).unwrap();
+        assert_eq!(props.num_rows, 1);
+        assert_eq!(props.num_hot_rows, 1);
+    }
+
+    #[test]
+    fn test_num_errors() {
+        let mut collector = UserPropertiesCollector::default();
+        let props = collector.finish();
+        assert_eq!(UserProperties::decode_num_errors(&props).unwrap(), 0);
+
+        let mut collector = UserPropertiesCollector::default();
+        let k = Key::from_raw(b"k").append_ts(1);
+        collector.add(&keys::data_key(k.encoded()), b"", DBEntryType::Put, 0, 0);
+        let props = collector.finish();
+        assert_eq!(UserProperties::decode_num_errors(&props).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_first_key() {
+        let a = keys::data_key(Key::from_raw(b"a").append_ts(1).encoded());
+        let b = keys::data_key(Key::from_raw(b"b").append_ts(2).encoded());
+        let v = Write::new(WriteType::Put, 1, None).to_bytes();
+        let collect = |flags| {
+            let mut collector = UserPropertiesCollector::new(CollectorOptions::from_flags(flags));
+            // Invalid keys are skipped.
+            collector.add(b"", &v, DBEntryType::Put, 0, 0);
+            collector.add(b"not_data", &v, DBEntryType::Put, 0, 0);
+            collector.add(&b, &v, DBEntryType::Put, 0, 0);
+            collector.add(&a, &v, DBEntryType::Put, 0, 0);
+            collector.finish()
+        };
+
+        let encoded = collect(MVCC_PROPERTIES | FIRST_KEY);
+        let props = UserProperties::decode(&encoded).unwrap();
+        // It's the first key added with ts, rather than the smallest one.
+        assert!(a < b);
+        assert_eq!(props.first_key, Some(b.clone()));
+        assert_eq!(props.mvcc.num_versions, 2);
+
+        let encoded = collect(MVCC_PROPERTIES);
+        assert!(!encoded.contains_key(super::PROP_FIRST_DATA_KEY.as_bytes()));
+        assert_eq!(UserProperties::decode(&encoded).unwrap().first_key, None);
+    }
+
+    #[test]
+    fn test_distinct_prefixes() {
+        let collect = |prefix_len, keys: &[&[u8]]| {
+            let opts = CollectorOptions {
+                distinct_prefix_len: prefix_len,
+                ..CollectorOptions::default()
+            };
+            let mut factory = UserPropertiesCollectorFactory::new(opts);
+            let mut collector = factory.create_table_properties_collector(0);
+            for key in keys {
+                for ts in (1..3).rev() {
+                    let k = keys::data_key(Key::from_raw(key).append_ts(ts).encoded());
+                    let v = Write::new(WriteType::Put, ts, None).to_bytes();
+                    collector.add(&k, &v, DBEntryType::Put, 0, 0);
+                }
+            }
+            collector.finish()
+        };
+
+        let keys: &[&[u8]] = &[b"t1_a", b"t1_b", b"t1_c", b"t2_a", b"t3_a", b"t3_b", b"t30"];
+        let props = UserProperties::decode(&collect(2, keys)).unwrap();
+        assert_eq!(props.distinct_prefixes, Some(3));
+        let props = UserProperties::decode(&collect(3, keys)).unwrap();
+        assert_eq!(props.distinct_prefixes, Some(4));
+        let props = UserProperties::decode(&collect(100, keys)).unwrap();
+        assert_eq!(props.distinct_prefixes, Some(7));
+        // Keys shorter than the prefix.
+        let keys: &[&[u8]] = &[b"", b"t", b"t1", b"t1_a"];
+        let props = UserProperties::decode(&collect(2, keys)).unwrap();
+        assert_eq!(props.distinct_prefixes, Some(3));
+        let props = UserProperties::decode(&collect(2, &[])).unwrap();
+        assert_eq!(props.distinct_prefixes, Some(0));
+
+        let encoded = collect(0, keys);
+        assert!(!encoded.contains_key(super::PROP_DISTINCT_PREFIXES.as_bytes()));
+        assert_eq!(UserProperties::decode(&encoded).unwrap().distinct_prefixes, None);
+    }
+
+    #[test]
+    fn test_suspect() {
+        let collect = |ratio, num_errors| {
+            let opts = CollectorOptions {
+                suspect_error_ratio: ratio,
+                ..CollectorOptions::default()
+            };
+            let mut factory = UserPropertiesCollectorFactory::new(opts);
+            let mut collector = factory.create_table_properties_collector(0);
+            for ts in 0..10 {
+                let k = keys::data_key(Key::from_raw(b"k").append_ts(ts).encoded());
+                let v = if ts < num_errors {
+                    b"garbage".to_vec()
+                } else {
+                    Write::new(WriteType::Put, ts, None).to_bytes()
+                };
+                collector.add(&k, &v, DBEntryType::Put, 0, 0);
+            }
+            UserProperties::decode(&collector.finish()).unwrap()
+        };
+
+        let props = collect(0.1, 5);
+        assert_eq!(props.num_errors, 5);
+        assert!(props.is_suspect());
+        assert!(!collect(0.1, 0).is_suspect());
+        // Exactly at the ratio.
+        assert!(!collect(0.5, 5).is_suspect());
+        assert!(collect(0.0, 1).is_suspect());
+        // Never suspect by default.
+        assert!(!collect(1.0, 10).is_suspect());
+        assert!(!UserProperties::new().is_suspect());
+    }
+
+    #[test]
+    fn test_descending_keys() {
+        let mut entries = vec![];
+        let writes = [("a", 3, WriteType::Delete),
+                      ("a", 2, WriteType::Put),
+                      ("a", 1, WriteType::Put),
+                      ("b", 1, WriteType::Put),
+                      ("c", 5, WriteType::Put),
+                      ("c", 4, WriteType::Lock),
+                      ("c", 2, WriteType::Put)];
+        for &(key, ts, write_type) in &writes {
+            let k = keys::data_key(Key::from_raw(key.as_bytes()).append_ts(ts).encoded());
+            entries.push((k, Write::new(write_type, ts, None).to_bytes()));
+        }
+        let collect = |ascending, entries: &[(Vec<u8>, Vec<u8>)]| {
+            let opts = CollectorOptions {
+                ascending: ascending,
+                safe_point: 3,
+                ..CollectorOptions::default()
+            };
+            let mut collector = UserPropertiesCollector::new(opts);
+            for &(ref k, ref v) in entries {
+                collector.add(k, v, DBEntryType::Put, 0, 0);
+            }
+            MvccProperties::decode(&collector.finish()).unwrap()
+        };
+
+        let props = collect(true, &entries);
+        assert_eq!(props.num_rows, 3);
+        assert_eq!(props.num_fully_deleted_rows, 1);
+        assert_eq!(props.num_puts_below_safepoint, 3);
+        assert_eq!(props.num_ts_anomalies, 0);
+
+        entries.reverse();
+        let reversed = collect(false, &entries);
+        assert_eq!(reversed.num_rows, 3);
+        assert_eq!(reversed.smallest_key, props.smallest_key);
+        assert_eq!(reversed.largest_key, props.largest_key);
+        assert_eq!(reversed.first_row_versions, 3);
+        assert_eq!(reversed.last_row_versions, 3);
+        assert_eq!(reversed.encode(), props.encode());
+
+        // Descending keys collected as ascending ones look corrupted.
+        let props = collect(true, &entries);
+        assert_eq!(props.num_rows, 3);
+        assert_eq!(props.num_ts_anomalies, 4);
+    }
+
+    #[test]
+    fn test_strict() {
+        let valid_value = |cf_mode| match cf_mode {
+            CfMode::Lock => Lock::new(LockType::Put, b"k".to_vec(), 1, 0, None).to_bytes(),
+            _ => Write::new(WriteType::Put, 1, None).to_bytes(),
+        };
+        let collect = |strict, cf_mode, value: &[u8]| {
+            let opts = CollectorOptions {
+                strict: strict,
+                cf_mode: cf_mode,
+                ..CollectorOptions::default()
+            };
+            let mut factory = UserPropertiesCollectorFactory::new(opts);
+            let mut collector = factory.create_table_properties_collector(0);
+            let k = keys::data_key(Key::from_raw(b"k").append_ts(2).encoded());
+            collector.add(&k, &valid_value(cf_mode), DBEntryType::Put, 0, 0);
+            let k = keys::data_key(Key::from_raw(b"k").append_ts(1).encoded());
+            collector.add(&k, value, DBEntryType::Put, 0, 0);
+            UserProperties::decode(&collector.finish()).unwrap()
+        };
+
+        let garbage = b"garbage";
+        let props = collect(false, CfMode::Write, garbage);
+        assert_eq!(props.num_errors, 1);
+        assert!(!props.fatal_errors);
+        let props = collect(true, CfMode::Write, garbage);
+        assert_eq!(props.num_errors, 1);
+        assert!(props.fatal_errors);
+        assert_eq!(props.mvcc.num_versions, 2);
+        let props = collect(true, CfMode::Lock, garbage);
+        assert_eq!(props.num_errors, 1);
+        assert!(props.fatal_errors);
+        assert_eq!(props.lock.unwrap().num_locks, 1);
+        // A write is malformed as a lock.
+        let props = collect(true, CfMode::Lock, &valid_value(CfMode::Write));
+        assert_eq!(props.num_errors, 1);
+        assert!(props.fatal_errors);
+
+        for &cf_mode in &[CfMode::Write, CfMode::Lock] {
+            let props = collect(true, cf_mode, &valid_value(cf_mode));
+            assert_eq!(props.num_errors, 0);
+            assert!(!props.fatal_errors);
+        }
+    }
+
+    #[test]
+    fn test_omit_default_fields() {
+        let mut collector = UserPropertiesCollector::default();
+        let k = Key::from_raw(b"k").append_ts(1);
+        let v = Write::new(WriteType::Put, 1, None).to_bytes();
+        collector.add(&keys::data_key(k.encoded()), &v, DBEntryType::Put, 0, 0);
+        let encoded = collector.finish();
+        assert!(!encoded.contains_key(super::PROP_NUM_ERRORS.as_bytes()));
+        assert!(!encoded.contains_key(super::PROP_NUM_NON_DATA_KEYS.as_bytes()));
+        assert!(!encoded.contains_key(PropKey::NumDeletes.as_bytes()));
+        assert_eq!(UserProperties::decode_num_errors(&encoded).unwrap(), 0);
+        let props = UserProperties::decode(&encoded).unwrap();
+        assert_eq!(props.num_errors, 0);
+        assert_eq!(props.num_non_data_keys, 0);
+        assert_eq!(props.mvcc.num_deletes, 0);
+        assert_eq!(props.mvcc.num_puts, 1);
+
+        // Baseline fields are always written.
+        let props = MvccProperties::new();
+        let encoded = props.encode();
+        for &k in super::PROP_KEYS {
+            let present = encoded.contains_key(k.as_bytes());
+            assert_eq!(present, k.default_value().is_none(), "{:?}", k);
+        }
+        let decoded = MvccProperties::decode(&encoded).unwrap();
+        for &k in super::PROP_KEYS {
+            assert_eq!(decoded.get(k), props.get(k), "{:?}", k);
+        }
+
+        // Version 1 has all fields.
+        let encoded = props.encode_with_version(super::PROP_VERSION_V1);
+        for &k in super::PROP_KEYS {
+            assert!(encoded.contains_key(k.as_bytes()), "{:?}", k);
+        }
+    }
+
+    #[test]
+    fn test_num_non_data_keys() {
+        let mut collector = UserPropertiesCollector::default();
+        let k = Key::from_raw(b"k").append_ts(1);
+        let v = Write::new(WriteType::Put, 1, None).to_bytes();
+        // A key of another CF without the data prefix.
+        collector.add(k.encoded(), &v, DBEntryType::Put, 0, 0);
+        // A corrupted value.
+        collector.add(&keys::data_key(k.encoded()), b"", DBEntryType::Put, 0, 0);
+        let mut encoded = collector.finish();
+        let props = UserProperties::decode(&encoded).unwrap();
+        assert_eq!(props.num_non_data_keys, 1);
+        assert_eq!(props.num_errors, 1);
+
+        // Older SSTs don't have it.
+        encoded.remove(super::PROP_NUM_NON_DATA_KEYS.as_bytes());
+        assert_eq!(UserProperties::decode(&encoded).unwrap().num_non_data_keys, 0);
+    }
+
+    #[test]
+    fn test_decode_user_properties() {
+        let cases = [("ab", 2, WriteType::Put), ("ab", 1, WriteType::Delete)];
+        let mut collector = UserPropertiesCollector::default();
+        for &(key, ts, write_type) in &cases {
+            let k = Key::from_raw(key.as_bytes()).append_ts(ts);
+            let k = keys::data_key(k.encoded());
+            let v = Write::new(write_type, ts, None).to_bytes();
+            collector.add(&k, &v, DBEntryType::Put, 0, 0);
+        }
+        collector.add(&keys::data_key(b"error"), b"error", DBEntryType::Put, 0, 0);
+        let mut encoded = collector.finish();
+        let props = UserProperties::decode(&encoded).unwrap();
+        assert_eq!(props.num_errors, 1);
+        assert_eq!(props.mvcc.num_rows, 1);
+        assert_eq!(props.mvcc.num_versions, 2);
+
+        // `num_errors` defaults to 0.
+        encoded.remove(super::PROP_NUM_ERRORS.as_bytes());
+        let props = UserProperties::decode(&encoded).unwrap();
+        assert_eq!(props.num_errors, 0);
+        assert_eq!(props.mvcc.num_versions, 2);
+
+        // Without the MVCC block.
+        let mut encoded = HashMap::new();
+        encoded.insert(super::PROP_NUM_ERRORS.as_bytes().to_owned(), super::encode_u64(3));
+        let props = UserProperties::decode(&encoded).unwrap();
+        assert_eq!(props.num_errors, 3);
+        assert!(props.mvcc.is_empty());
+
+        // A partial MVCC block is corrupted.
+        encoded.insert(PropKey::MinTs.as_bytes().to_owned(), super::encode_u64(1));
+        assert!(UserProperties::decode(&encoded).is_err());
+    }
+
+    #[test]
+    fn test_last_row_without_ts() {
+        let mut collector = UserPropertiesCollector::default();
+        for ts in (1..10).rev() {
+            let k = Key::from_raw(b"k").append_ts(ts);
+            let k = keys::data_key(k.encoded());
+            let v = Write::new(WriteType::Put, ts, None).to_bytes();
+            collector.add(&k, &v, DBEntryType::Put, 0, 0);
+        }
+        assert_eq!(collector.last_row, keys::data_key(Key::from_raw(b"k").encoded()));
+        assert_eq!(collector.row_versions, 9);
+    }
+
+    #[test]
+    fn test_num_rows() {
+        let cases = [("a", 3), ("a", 2), ("a", 1), ("ab", 1), ("b", 4), ("b", 2), ("c", 1)];
+        let mut collector = UserPropertiesCollector::default();
+        let mut rows = Vec::new();
+        for &(key, ts) in &cases {
+            let k = Key::from_raw(key.as_bytes()).append_ts(ts);
+            let k = keys::data_key(k.encoded());
+            let v = Write::new(WriteType::Put, ts, None).to_bytes();
+            collector.add(&k, &v, DBEntryType::Put, 0, 0);
+            if rows.last() != Some(&key) {
+                rows.push(key);
+            }
+        }
+        let props = MvccProperties::decode(&collector.finish()).unwrap();
+        assert_eq!(props.num_rows, rows.len() as u64);
+        assert_eq!(props.num_versions, cases.len() as u64);
+        assert_eq!(props.max_row_versions, 3);
+    }
+
+    #[test]
+    fn test_cf_name() {
+        let mut opts = CollectorOptions::default();
+        opts.cf_name = "write".to_owned();
+        let mut factory = UserPropertiesCollectorFactory::new(opts);
+        let mut collector = factory.create_table_properties_collector(0);
+        let props = UserProperties::decode(&collector.finish()).unwrap();
+        assert_eq!(props.cf_name, "write");
+
+        let mut collector = UserPropertiesCollector::default();
+        let props = UserProperties::decode(&collector.finish()).unwrap();
+        assert!(props.cf_name.is_empty());
+    }
+
+    fn collect_versions(rows: &[(&str, u64)]) -> MvccProperties {
+        let mut collector = UserPropertiesCollector::default();
+        for &(key, versions) in rows {
+            for ts in (0..versions).rev() {
+                let k = Key::from_raw(key.as_bytes()).append_ts(ts);
+                let k = keys::data_key(k.encoded());
+                let v = Write::new(WriteType::Put, ts, None).to_bytes();
+                collector.add(&k, &v, DBEntryType::Put, 0, 0);
+            }
+        }
+        MvccProperties::decode(&collector.finish()).unwrap()
+    }
+
+    #[test]
+    fn test_boundary_row_versions() {
+        let props = collect_versions(&[("a", 2), ("b", 1), ("c", 3)]);
+        assert_eq!(props.first_row_versions, 2);
+        assert_eq!(props.last_row_versions, 3);
+
+        let props = collect_versions(&[("a", 4)]);
+        assert_eq!(props.first_row_versions, 4);
+        assert_eq!(props.last_row_versions, 4);
+
+        // Row "c" straddles the first two SSTs, and row "e" straddles all the
+        // last three SSTs.
+        let ssts = [collect_versions(&[("a", 1), ("c", 3)]),
+                    collect_versions(&[("c", 2), ("d", 1), ("e", 1)]),
+                    collect_versions(&[("e", 2)]),
+                    collect_versions(&[("e", 2), ("f", 1)])];
+        let mut naive = MvccProperties::new();
+        let mut stitched = MvccProperties::new();
+        for props in &ssts {
+            naive.add(props);
+            stitched.add_adjacent(props);
+        }
+        assert_eq!(naive.num_rows, 8);
+        assert_eq!(naive.max_row_versions, 3);
+        assert_eq!(stitched.num_rows, 5);
+        assert_eq!(stitched.max_row_versions, 5);
+        assert_eq!(stitched.first_row_versions, 1);
+        assert_eq!(stitched.last_row_versions, 1);
+        assert_eq!(stitched.num_versions, naive.num_versions);
+    }
+
+    #[test]
+    fn test_shares_boundary_row() {
+        // Row "b" is split into two SSTs.
+        let left = collect_versions(&[("a", 1), ("b", 2)]);
+        let right = collect_versions(&[("b", 3), ("c", 1)]);
+        assert!(left.shares_boundary_row(&right));
+        assert!(!right.shares_boundary_row(&left));
+        let versions = left.last_row_versions + right.first_row_versions;
+        assert_eq!(versions, 5);
+        let mut total = left.clone();
+        total.add_adjacent(&right);
+        assert_eq!(total.max_row_versions, versions);
+
+        let right = collect_versions(&[("c", 3)]);
+        assert!(!left.shares_boundary_row(&right));
+        assert!(!left.shares_boundary_row(&MvccProperties::new()));
+        assert!(!MvccProperties::new().shares_boundary_row(&left));
+        // Older SSTs don't have the key range.
+        let mut left = left.clone();
+        left.largest_key.clear();
+        assert!(!left.shares_boundary_row(&left.clone()));
+    }
+
+    #[test]
+    fn test_num_fully_deleted_rows() {
+        let cases = [("a", 5, WriteType::Put), // Shadows the delete.
+                     ("a", 3, WriteType::Delete),
+                     ("b", 6, WriteType::Rollback),
+                     ("b", 5, WriteType::Delete), // The latest data version.
+                     ("b", 3, WriteType::Put),
+                     ("c", 2, WriteType::Delete)];
+        let mut collector = UserPropertiesCollector::default();
+        for &(key, ts, write_type) in &cases {
+            let k = Key::from_raw(key.as_bytes()).append_ts(ts);
+            let k = keys::data_key(k.encoded());
+            let v = Write::new(write_type, ts, None).to_bytes();
+            collector.add(&k, &v, DBEntryType::Put, 0, 0);
+        }
+        let props = MvccProperties::decode(&collector.finish()).unwrap();
+        assert_eq!(props.num_rows, 3);
+        assert_eq!(props.num_deletes, 3);
+        assert_eq!(props.num_fully_deleted_rows, 2);
+    }
+
+    #[test]
+    fn test_cf_id() {
+        let mut factory = UserPropertiesCollectorFactory::default();
+        let mut collector = factory.create_table_properties_collector(3);
+        let props = UserProperties::decode(&collector.finish()).unwrap();
+        assert_eq!(props.cf_id, Some(3));
+
+        let mut collector = UserPropertiesCollector::default();
+        let props = UserProperties::decode(&collector.finish()).unwrap();
+        assert_eq!(props.cf_id, None);
+    }
+
+    #[derive(Default)]
+    struct MockMetrics {
+        ssts: AtomicUsize,
+        entries: AtomicUsize,
+        errors: AtomicUsize,
+    }
+
+    impl CollectorMetrics for MockMetrics {
+        fn on_finish(&self, num_entries: u64, num_errors: u64) {
+            self.ssts.fetch_add(1, Ordering::SeqCst);
+            self.entries.fetch_add(num_entries as usize, Ordering::SeqCst);
+            self.errors.fetch_add(num_errors as usize, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn test_collector_metrics() {
+        let metrics = Arc::new(MockMetrics::default());
+        let mut factory = UserPropertiesCollectorFactory::default();
+        factory.set_metrics(metrics.clone());
+        let v = Write::new(WriteType::Put, 1, None).to_bytes();
+        for i in 0..2 {
+            let mut collector = factory.create_table_properties_collector(0);
+            for ts in 1..4 {
+                let k = Key::from_raw(b"k").append_ts(ts);
+                collector.add(&keys::data_key(k.encoded()), &v, DBEntryType::Put, 0, 0);
+            }
+            collector.add(&keys::data_key(b"error"), &v, DBEntryType::Put, 0, 0);
+            // Nothing is reported before finishing.
+            assert_eq!(metrics.ssts.load(Ordering::SeqCst), i);
+            collector.finish();
+            assert_eq!(metrics.ssts.load(Ordering::SeqCst), i + 1);
+            assert_eq!(metrics.entries.load(Ordering::SeqCst), (i + 1) * 4);
+            assert_eq!(metrics.errors.load(Ordering::SeqCst), i + 1);
+        }
+
+        // Collectors without metrics report nothing.
+        let mut collector = UserPropertiesCollector::default();
+        collector.finish();
+        assert_eq!(metrics.ssts.load(Ordering::SeqCst), 2);
+        let mut collector = UserPropertiesCollector::default();
+        collector.set_metrics(metrics.clone());
+        collector.finish();
+        assert_eq!(metrics.ssts.load(Ordering::SeqCst), 3);
+        assert_eq!(metrics.entries.load(Ordering::SeqCst), 8);
+    }
+
+    #[test]
+    fn test_property_registry() {
+        let mut registry = PropertyRegistry::new();
+        let num_deletes = |_: &[u8], _: &[u8], t| if let DBEntryType::Delete = t { 1 } else { 0 };
+        registry.register("test.num_deletes", CombineRule::Sum, Box::new(num_deletes)).unwrap();
+        let value_len = |_: &[u8], v: &[u8], _| v.len() as u64;
+        registry.register("test.max_value_len", CombineRule::Max, Box::new(value_len)).unwrap();
+        // Deletes have no values.
+        let put_len = |_: &[u8], v: &[u8], t| match t {
+            DBEntryType::Put => v.len() as u64,
+            _ => u64::MAX,
+        };
+        registry.register("test.min_put_len", CombineRule::Min, Box::new(put_len)).unwrap();
+        assert!(registry.register("test.num_deletes", CombineRule::Sum, Box::new(num_deletes))
+            .is_err());
+
+        let mut factory = UserPropertiesCollectorFactory::default();
+        factory.set_registry(Arc::new(registry));
+        let mut collect = |rows: &[(&[u8], u64, Option<&[u8]>)]| {
+            let mut collector = factory.create_table_properties_collector(0);
+            for &(k, ts, v) in rows {
+                let k = keys::data_key(Key::from_raw(k).append_ts(ts).encoded());
+                match v {
+                    Some(v) => {
+                        let v = Write::new(WriteType::Put, ts, Some(v.to_vec())).to_bytes();
+                        collector.add(&k, &v, DBEntryType::Put, 0, 0);
+                    }
+                    None => collector.add(&k, b"", DBEntryType::Delete, 0, 0),
+                }
+            }
+            collector.finish()
+        };
+        let a = collect(&[(b"a", 2, None), (b"a", 1, Some(b"v")), (b"b", 1, Some(b"value"))]);
+        let b = collect(&[(b"c", 3, Some(b"vv")), (b"d", 2, None), (b"d", 1, None)]);
+        let registry = PropertyRegistry::new();
+        assert!(registry.decode(&a).unwrap().is_empty());
+
+        let mut registry = PropertyRegistry::new();
+        registry.register("test.num_deletes", CombineRule::Sum, Box::new(|_, _, _| 0)).unwrap();
+        registry.register("test.max_value_len", CombineRule::Max, Box::new(|_, _, _| 0)).unwrap();
+        registry.register("test.min_put_len", CombineRule::Min, Box::new(|_, _, _| 0)).unwrap();
+        registry.register("test.absent", CombineRule::Sum, Box::new(|_, _, _| 0)).unwrap();
+        let mut res = registry.decode(&a).unwrap();
+        assert_eq!(res.len(), 3);
+        assert_eq!(res["test.num_deletes"], 1);
+        let put_len = |v: &[u8]| Write::new(WriteType::Put, 1, Some(v.to_vec())).to_bytes().len();
+        assert_eq!(res["test.max_value_len"], put_len(b"value") as u64);
+        assert_eq!(res["test.min_put_len"], put_len(b"v") as u64);
+        // The built-in properties are still there.
+        let user_props = UserProperties::decode(&a).unwrap();
+        assert_eq!(user_props.mvcc.num_rows, 2);
+
+        let b = registry.decode(&b).unwrap();
+        assert_eq!(b["test.num_deletes"], 2);
+        registry.combine(&mut res, &b);
+        assert_eq!(res["test.num_deletes"], 3);
+        assert_eq!(b["test.min_put_len"], put_len(b"vv") as u64);
+        assert_eq!(res["test.max_value_len"], put_len(b"value") as u64);
+        assert_eq!(res["test.min_put_len"], put_len(b"v") as u64);
+        assert!(!res.contains_key("test.absent"));
+    }
+
+    #[test]
+    fn test_decode_namespaced() {
+        let mut registry = PropertyRegistry::new();
+        registry.register("count", CombineRule::Sum, Box::new(|_, _, _| 1)).unwrap();
+        // It doesn't collide with the built-in "tikv.num_rows".
+        registry.register("num_rows", CombineRule::Sum, Box::new(|_, _, _| 1)).unwrap();
+        let registry = Arc::new(registry);
+        let mut collector = UserPropertiesCollector::default();
+        collector.set_registry(registry.clone());
+        let v = Write::new(WriteType::Put, 1, None).to_bytes();
+        for ts in 1..3 {
+            let k = Key::from_raw(b"k").append_ts(ts);
+            collector.add(&keys::data_key(k.encoded()), &v, DBEntryType::Put, 0, 0);
+        }
+        let mut props = collector.finish();
+        assert_eq!(props[b"tikv.custom.count".as_ref()], super::encode_u64(2));
+        // Keys of RocksDB and other collectors, whose values can't be decoded.
+        props.insert(b"rocksdb.num.entries".to_vec(), b"x".to_vec());
+        props.insert(b"count".to_vec(), b"x".to_vec());
+        props.insert(b"custom.count".to_vec(), vec![]);
+
+        let namespaced = NamespacedProperties::new(&props, "tikv.");
+        assert!(namespaced.get_raw("rocksdb.num.entries").is_none());
+        match namespaced.decode_u64("count") {
+            Err(codec::Error::KeyNotFound) => {}
+            res => panic!("expect KeyNotFound, got {:?}", res),
+        }
+        assert_eq!(namespaced.decode_u64("custom.count").unwrap(), 2);
+        assert_eq!(namespaced.get_raw("num_rows"),
+                   props.get(PropKey::NumRows.as_bytes()).map(|v| v.as_slice()));
+
+        let res = registry.decode(&props).unwrap();
+        assert_eq!(res["count"], 2);
+        assert_eq!(res["num_rows"], 2);
+        let props = UserProperties::decode(&props).unwrap();
+        assert_eq!(props.mvcc.num_rows, 1);
+    }
+
+    #[test]
+    fn test_prefix_filter() {
+        let collector = |prefix: &[u8]| {
+            let opts = CollectorOptions {
+                prefix_filter: Some(prefix.to_vec()),
+                ..CollectorOptions::default()
+            };
+            UserPropertiesCollectorFactory::new(opts).create_table_properties_collector(0)
+        };
+        let mut tenant1 = collector(b"t1_");
+        let mut tenant2 = collector(b"t2_");
+        let mut all = UserPropertiesCollectorFactory::default()
+            .create_table_properties_collector(0);
+        // Keys longer than an encoding group, and keys padded with zeros.
+        let cases = [("t1_a", 2),
+                     ("t1_a", 1),
+                     ("t1_aaaaaaaaaaaa", 1),
+                     ("t1", 1),
+                     ("t2_", 3),
+                     ("t3_a", 1)];
+        for &(key, ts) in &cases {
+            let k = keys::data_key(Key::from_raw(key.as_bytes()).append_ts(ts).encoded());
+            let v = Write::new(WriteType::Put, ts, None).to_bytes();
+            for c in &mut [&mut tenant1, &mut tenant2, &mut all] {
+                c.add(&k, &v, DBEntryType::Put, 0, 0);
+            }
+        }
+        // Entries which are not data keys or not decodable are ignored too.
+        for c in &mut [&mut tenant1, &mut tenant2] {
+            c.add(b"", b"v", DBEntryType::Put, 0, 0);
+            c.add(b"t1_", b"v", DBEntryType::Put, 0, 0);
+            c.add(b"zt1_", b"v", DBEntryType::Put, 0, 0);
+        }
+
+        let props = UserProperties::decode(&tenant1.finish()).unwrap();
+        assert_eq!(props.entry_count, 3);
+        assert_eq!(props.num_errors + props.num_non_data_keys, 0);
+        assert_eq!((props.mvcc.num_rows, props.mvcc.num_versions), (2, 3));
+        let props = UserProperties::decode(&tenant2.finish()).unwrap();
+        assert_eq!(props.entry_count, 1);
+        assert_eq!((props.mvcc.num_rows, props.mvcc.num_versions), (1, 1));
+        let props = UserProperties::decode(&all.finish()).unwrap();
+        assert_eq!(props.entry_count, 6);
+
+        // Keys of raw key-values are not encoded.
+        let opts = CollectorOptions {
+            cf_mode: CfMode::Raw,
+            prefix_filter: Some(b"t1_".to_vec()),
+            ..CollectorOptions::default()
+        };
+        let mut raw = UserPropertiesCollector::new(opts);
+        raw.add(b"zt1_a", b"v", DBEntryType::Put, 0, 0);
+        raw.add(b"zt2_a", b"v", DBEntryType::Put, 0, 0);
+        let props = UserProperties::decode(&raw.finish()).unwrap();
+        assert_eq!(props.raw.unwrap().num_keys, 1);
+    }
+
+    #[test]
+    fn test_region_id() {
+        let opts = CollectorOptions { region_id: Some(u64::MAX), ..CollectorOptions::default() };
+        let mut factory = UserPropertiesCollectorFactory::new(opts);
+        let mut collector = factory.create_table_properties_collector(0);
+        let encoded = collector.finish();
+        assert_eq!(encoded.decode_u64(super::PROP_REGION_ID).unwrap(), u64::MAX);
+        let props = UserProperties::decode(&encoded).unwrap();
+        assert_eq!(props.region_id, Some(u64::MAX));
+
+        let mut collector = UserPropertiesCollectorFactory::default()
+            .create_table_properties_collector(0);
+        let encoded = collector.finish();
+        assert!(!encoded.contains_key(super::PROP_REGION_ID.as_bytes()));
+        assert_eq!(UserProperties::decode(&encoded).unwrap().region_id, None);
+    }
+
+    #[test]
+    fn test_rows_per_kb() {
+        let mut mvcc = MvccProperties::new();
+        mvcc.num_rows = 10;
+        let mut size = SizeProperties::default();
+        assert_eq!(rows_per_kb(&mvcc, &size), 0.0);
+
+        size.total_key_size = 1024;
+        size.total_value_size = 1024;
+        assert_eq!(rows_per_kb(&mvcc, &size), 5.0);
+
+        mvcc.num_rows = 0;
+        assert_eq!(rows_per_kb(&mvcc, &size), 0.0);
+    }
+
+    #[test]
+    fn test_versions_below_safepoint() {
+        let mut opts = CollectorOptions::default();
+        opts.safe_point = 5;
+        let mut collector = UserPropertiesCollector::new(opts);
+        for &ts in &[7, 5, 4, 1] {
+            let k = Key::from_raw(b"k").append_ts(ts);
+            let k = keys::data_key(k.encoded());
+            let v = Write::new(WriteType::Put, ts, None).to_bytes();
+            collector.add(&k, &v, DBEntryType::Put, 0, 0);
+        }
+        let props = MvccProperties::decode(&collector.finish()).unwrap();
+        assert_eq!(props.versions_below_safepoint, 2);
+        assert_eq!(props.versions_above_safepoint(), 2);
+        assert_eq!(props.earliest_commit_ts(), 1);
+        assert_eq!(props.latest_commit_ts(), 7);
+
+        // Nothing is counted without a safe point.
+        let props = collect_versions(&[("a", 3)]);
+        assert_eq!(props.versions_below_safepoint, 0);
+        assert_eq!(props.versions_above_safepoint(), 3);
+    }
+
+    #[test]
+    fn test_num_puts_below_safepoint() {
+        // (row, ts, write type), versions of a row are in descending ts order.
+        let cases = [// The newest version is kept even if it is below the safe point.
+                     ("a", 4, WriteType::Put),
+                     ("a", 3, WriteType::Put),
+                     ("a", 2, WriteType::Put),
+                     // Locks and rollbacks are not versions that shadow others.
+                     ("b", 4, WriteType::Rollback),
+                     ("b", 3, WriteType::Lock),
+                     ("b", 2, WriteType::Put),
+                     ("b", 1, WriteType::Put),
+                     // Puts shadowed by a delete can be removed, the delete is not counted.
+                     ("c", 3, WriteType::Delete),
+                     ("c", 2, WriteType::Put),
+                     ("c", 1, WriteType::Delete),
+                     // Shadowed puts above the safe point are not counted.
+                     ("d", 9, WriteType::Put),
+                     ("d", 8, WriteType::Put),
+                     ("d", 7, WriteType::Put),
+                     ("d", 1, WriteType::Put)];
+        let mut opts = CollectorOptions::default();
+        opts.safe_point = 5;
+        let mut factory = UserPropertiesCollectorFactory::new(opts);
+        let mut collector = factory.create_table_properties_collector(0);
+        for &(key, ts, write_type) in &cases {
+            let k = Key::from_raw(key.as_bytes()).append_ts(ts);
+            let k = keys::data_key(k.encoded());
+            let v = Write::new(write_type, ts, None).to_bytes();
+            collector.add(&k, &v, DBEntryType::Put, 0, 0);
+        }
+        let props = MvccProperties::decode(&collector.finish()).unwrap();
+        assert_eq!(props.num_puts_below_safepoint, 2 + 1 + 1 + 1);
+        assert_eq!(props.versions_below_safepoint, 11);
+
+        let mut total = props.clone();
+        total.add(&props);
+        assert_eq!(total.num_puts_below_safepoint, 10);
+
+        // Nothing is counted without a safe point.
+        let props = collect_versions(&[("a", 3)]);
+        assert_eq!(props.num_puts_below_safepoint, 0);
+    }
+
+    #[test]
+    fn test_encode_order() {
+        let mut props = UserProperties::new();
+        props.cf_name = "write".to_owned();
+        props.mvcc.num_rows = 3;
+        props.mvcc.smallest_key = b"a".to_vec();
+        let a: Vec<_> = props.encode().into_iter().collect();
+        let b: Vec<_> = props.clone().encode().into_iter().collect();
+        assert_eq!(a, b);
+        let mut keys: Vec<_> = a.iter().map(|&(ref k, _)| k.clone()).collect();
+        keys.sort();
+        assert_eq!(keys, a.into_iter().map(|(k, _)| k).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_add_saturating() {
+        let mut props = MvccProperties::new();
+        props.num_rows = u64::MAX - 1;
+        props.num_puts = u64::MAX - 1;
+        props.num_versions = u64::MAX - 1;
+        props.add(&props.clone());
+        assert_eq!(props.num_rows, u64::MAX);
+        assert_eq!(props.num_puts, u64::MAX);
+        assert_eq!(props.num_versions, u64::MAX);
+        assert_eq!(props.num_deletes, 0);
+    }
+
+    #[test]
+    fn test_encode_to_bytes() {
+        let mut props = collect_versions(&[("a", 2), ("b", 1)]);
+        props.num_hot_rows = 1;
+        let data = props.encode_to_bytes();
+        let decoded = MvccProperties::decode_from_bytes(&data).unwrap();
+        assert_eq!(decoded.encode(), props.encode());
+        assert_eq!(decoded.encode_to_bytes(), data);
+
+        // Truncated data is corrupted.
+        assert!(MvccProperties::decode_from_bytes(&data[..data.len() - 1]).is_err());
+        assert!(MvccProperties::decode_from_bytes(b"").is_err());
+    }
+
+    #[test]
+    fn test_wire_format() {
+        let mut props = MvccProperties::new();
+        props.min_ts = 1;
+        props.max_ts = 2;
+        props.num_rows = 3;
+        props.num_puts = 4;
+        props.puts_valid = true;
+        props.num_deletes = 6;
+        props.num_rollbacks = 7;
+        props.num_merges = 8;
+        props.num_versions = 9;
+        props.max_row_versions = 10;
+        props.num_hot_rows = 11;
+        props.num_ts_anomalies = 12;
+        props.num_fully_deleted_rows = 13;
+        props.num_pure_tombstone_rows = 14;
+        props.versions_below_safepoint = 15;
+        props.num_puts_below_safepoint = 16;
+        props.first_row_versions = 17;
+        props.last_row_versions = 18;
+        props.min_row_latest_ts = 19;
+        props.max_value_size = 20;
+        props.entry_type_puts = 21;
+        props.entry_type_deletes = 22;
+        props.entry_type_other = 23;
+        props.num_duplicate_versions = 24;
+        props.max_version_gap = 25;
+        props.min_start_ts = 26;
+        props.max_start_ts = 27;
+        props.num_inlined_values = 28;
+        props.num_pointer_values = 29;
+        props.deletes_seen = true;
+        props.num_non_data_versions = 31;
+        props.total_row_key_len = 32;
+        props.num_single_version_rows = 33;
+        props.versions_histogram = [1, 2, 3, 4, 5];
+        props.smallest_key = b"a".to_vec();
+        props.largest_key = b"b".to_vec();
+        props.bloom_prefix_len = 7;
+
+        // The wire format must not change, or nodes of other versions can't
+        // decode the properties.
+        let mut expected = vec![0, 0, 0, 0, 0, 0, 0, 1, 33];
+        expected.extend_from_slice(&[1, 2, 3, 4, 1, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17,
+                                     18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 1, 31, 32,
+                                     33]);
+        // No flags, the histogram, the keys and the sketches.
+        expected.extend_from_slice(&[0, 5, 1, 2, 3, 4, 5, 2, b'a', 2, b'b', 0, 0, 7]);
+        assert_eq!(props.to_wire(), expected);
+        let decoded = MvccProperties::from_wire(&expected).unwrap();
+        assert_eq!(decoded.encode(), props.encode());
+    }
+
+    #[test]
+    fn test_wire() {
+        let mut opts = CollectorOptions::from_flags(MVCC_PROPERTIES | KEY_CARDINALITY |
+                                                    PREFIX_BLOOM);
+        opts.safe_point = 2;
+        let mut collector = UserPropertiesCollector::new(opts);
+        for &(key, ts) in &[("a", 3), ("a", 1), ("b", 2)] {
+            let k = keys::data_key(Key::from_raw(key.as_bytes()).append_ts(ts).encoded());
+            let v = Write::new(WriteType::Put, ts, None).to_bytes();
+            collector.add(&k, &v, DBEntryType::Put, 0, 0);
+        }
+        let props = MvccProperties::decode(&collector.finish()).unwrap();
+        let data = props.to_wire();
+        let decoded = MvccProperties::from_wire(&data).unwrap();
+        assert_eq!(decoded.encode(), props.encode());
+        assert_eq!(decoded.collected_flags, props.collected_flags);
+        assert_eq!(decoded.to_wire(), data);
+
+        let props = MvccProperties::with(1, 9, 3, 4, 5, 2);
+        let decoded = MvccProperties::from_wire(&props.to_wire()).unwrap();
+        assert_eq!(decoded.collected_flags, None);
+        assert_eq!(decoded.encode(), props.encode());
+
+        // Truncated data is corrupted.
+        for len in 0..data.len() {
+            assert!(MvccProperties::from_wire(&data[..len]).is_err(), "{}", len);
+        }
+
+        // The version is checked.
+        let mut future = props.to_wire();
+        future[..8].copy_from_slice(&super::encode_u64(super::WIRE_VERSION as u64 + 1));
+        match MvccProperties::from_wire(&future) {
+            Err(codec::Error::UnsupportedPropVersion(v)) => {
+                assert_eq!(v, super::WIRE_VERSION + 1)
+            }
+            res => panic!("expect unsupported version error, got {:?}", res),
+        }
+
+        // Integer fields can be appended or missing.
+        let mut head = super::encode_u64(super::WIRE_VERSION as u64);
+        head.encode_var_u64(super::PROP_KEYS.len() as u64).unwrap();
+        for &k in super::PROP_KEYS {
+            head.encode_var_u64(props.get(k)).unwrap();
+        }
+        let tail = props.to_wire().split_off(head.len());
+        let build = |num_fields: usize, extra: &[u64]| {
+            let mut buf = super::encode_u64(super::WIRE_VERSION as u64);
+            buf.encode_var_u64((num_fields + extra.len()) as u64).unwrap();
+            for &k in &super::PROP_KEYS[..num_fields] {
+                buf.encode_var_u64(props.get(k)).unwrap();
+            }
+            for &v in extra {
+                buf.encode_var_u64(v).unwrap();
+            }
+            buf.extend_from_slice(&tail);
+            buf
+        };
+        let newer = build(super::PROP_KEYS.len(), &[42, 43]);
+        let decoded = MvccProperties::from_wire(&newer).unwrap();
+        assert_eq!(decoded.encode(), props.encode());
+        // Fields up to `MaxRowVersions` are required.
+        let older = build(10, &[]);
+        let decoded = MvccProperties::from_wire(&older).unwrap();
+        assert_eq!(decoded.num_rows, 3);
+        assert_eq!(decoded.max_row_versions, 2);
+        assert_eq!(decoded.num_deletes, 0);
+        assert!(MvccProperties::from_wire(&build(9, &[])).is_err());
+    }
+
+    #[test]
+    fn test_lock_properties() {
+        let mut opts = CollectorOptions::default();
+        opts.cf_mode = CfMode::Lock;
+        let mut collector = UserPropertiesCollector::new(opts);
+        let entries = [(b"a", 5, DBEntryType::Put),
+                       (b"b", 3, DBEntryType::Put),
+                       (b"c", 9, DBEntryType::Put),
+                       (b"d", 1, DBEntryType::Delete)];
+        for &(k, ts, entry_type) in &entries {
+            let k = keys::data_key(Key::from_raw(k).encoded());
+            let v = Lock::new(LockType::Put, b"a".to_vec(), ts, 0, None).to_bytes();
+            collector.add(&k, &v, entry_type, 0, 0);
+        }
+        // A corrupted lock.
+        collector.add(&keys::data_key(b"e"), b"x", DBEntryType::Put, 0, 0);
+
+        let props = UserProperties::decode(&collector.finish()).unwrap();
         assert_eq!(props.num_errors, 1);
+        assert!(props.mvcc.is_empty());
+        let lock = props.lock.unwrap();
+        assert_eq!(lock.num_locks, 3);
+        assert_eq!(lock.min_ts, 3);
+        assert_eq!(lock.max_ts, 9);
+
+        let mut total = LockProperties::new();
+        total.add(&lock);
+        total.add(&LockProperties::new());
+        assert_eq!(total.num_locks, 3);
+        assert_eq!(total.min_ts, 3);
+
+        // Lock properties are not collected by default.
+        let props = UserProperties::decode(&UserPropertiesCollector::default().finish()).unwrap();
+        assert!(props.lock.is_none());
+        assert!(props.default_cf.is_none());
+    }
+
+    #[test]
+    fn test_entry_types() {
+        for &strict in &[false, true] {
+            let mut flags = MVCC_PROPERTIES;
+            if strict {
+                flags = flags | STRICT_ENTRY_TYPES;
+            }
+            let mut collector = UserPropertiesCollector::new(CollectorOptions::from_flags(flags));
+            let entry_types = [DBEntryType::Put,
+                               DBEntryType::Delete,
+                               DBEntryType::SingleDelete,
+                               DBEntryType::Merge,
+                               DBEntryType::Other];
+            for (i, &entry_type) in entry_types.iter().enumerate() {
+                let k = Key::from_raw(b"k").append_ts(10 - i as u64);
+                let k = keys::data_key(k.encoded());
+                let v = Write::new(WriteType::Put, 1, None).to_bytes();
+                collector.add(&k, &v, entry_type, 0, 0);
+            }
+            let props = UserProperties::decode(&collector.finish()).unwrap();
+            assert_eq!(props.mvcc.num_versions, 1);
+            assert_eq!(props.mvcc.num_puts, 1);
+            assert_eq!(props.mvcc.num_merges, 1);
+            assert_eq!(props.num_errors, if strict { 1 } else { 0 });
+        }
+    }
+
+    #[test]
+    fn test_collect_random_values() {
+        let mut rng = rand::thread_rng();
+        for &cf_mode in &[CfMode::Write, CfMode::Lock] {
+            let mut collector = UserPropertiesCollector::new(CollectorOptions {
+                cf_mode: cf_mode,
+                ..CollectorOptions::default()
+            });
+            let parse_ok = |v: &[u8]| match cf_mode {
+                CfMode::Lock => Lock::parse(v).is_ok(),
+                _ => Write::parse(v).is_ok(),
+            };
+            // A varint length of -1 and a huge one for the primary key of a lock.
+            let mut values = vec![b"P\x01".to_vec(),
+                                  b"P\xfe\xff\xff\xff\xff\xff\xff\xff\x7f".to_vec()];
+            for _ in 0..1000 {
+                let mut v = vec![0; rng.gen_range(0, 32)];
+                rng.fill_bytes(&mut v);
+                // Make the values more likely to be parsed further than the flag.
+                if !v.is_empty() && rng.gen_range(0, 2) == 0 {
+                    v[0] = b'P';
+                }
+                values.push(v);
+            }
+            let mut num_errors = 0;
+            let mut num_empty = 0;
+            for (ts, v) in values.iter().enumerate() {
+                if !parse_ok(v) {
+                    num_errors += 1;
+                }
+                if v.is_empty() {
+                    num_empty += 1;
+                }
+                let k = Key::from_raw(b"k").append_ts(ts as u64);
+                let k = keys::data_key(k.encoded());
+                collector.add(&k, v, DBEntryType::Put, 0, 0);
+            }
+            let props = UserProperties::decode(&collector.finish()).unwrap();
+            assert_eq!(props.num_errors, num_errors);
+            match cf_mode {
+                CfMode::Lock => {
+                    let num_locks = props.lock.unwrap().num_locks;
+                    assert_eq!(num_locks, values.len() as u64 - num_errors);
+                }
+                // Empty values are rejected before counting the version.
+                _ => assert_eq!(props.mvcc.num_versions, values.len() as u64 - num_empty),
+            }
+        }
+    }
+
+    #[test]
+    fn test_estimated_distinct_rows() {
+        let collect = |rows: ::std::ops::Range<u64>| {
+            let flags = MVCC_PROPERTIES | KEY_CARDINALITY;
+            let mut collector = UserPropertiesCollector::new(CollectorOptions::from_flags(flags));
+            for i in rows {
+                let k = Key::from_raw(format!("k{:08}", i).as_bytes()).append_ts(1);
+                let k = keys::data_key(k.encoded());
+                let v = Write::new(WriteType::Put, 1, None).to_bytes();
+                collector.add(&k, &v, DBEntryType::Put, 0, 0);
+            }
+            MvccProperties::decode(&collector.finish()).unwrap()
+        };
+
+        // The two SSTs share 2000 rows.
+        let mut props = collect(0..6000);
+        props.add(&collect(4000..10000));
+        assert_eq!(props.num_rows, 12000);
+        let estimated = props.estimated_distinct_rows() as f64;
+        assert!((estimated - 10000.0).abs() < 10000.0 * 0.1, "{}", estimated);
+
+        // Small cardinalities are accurate.
+        let estimated = collect(0..10).estimated_distinct_rows();
+        assert!(estimated >= 9 && estimated <= 11, "{}", estimated);
+
+        // Without a sketch in any operand, fall back to `num_rows`.
+        props.add(&collect_versions(&[("a", 1)]));
+        assert!(props.key_hll.is_empty());
+        assert_eq!(props.estimated_distinct_rows(), 12001);
+    }
+
+    #[test]
+    fn test_mvcc_properties_aggregator() {
+        let props = vec![collect_versions(&[("a", 2), ("b", 1)]),
+                         MvccProperties::new(),
+                         collect_versions(&[("c", 3)])];
+        let mut aggregator = MvccPropertiesAggregator::new();
+        for p in &props {
+            aggregator.push(&p.encode()).unwrap();
+        }
+        let res = aggregator.finish();
+        let expected = MvccProperties::from_iter(props);
+        assert_eq!(res.encode(), expected.encode());
+        assert_eq!(res.num_rows, 3);
+        assert_eq!(res.num_versions, 6);
+
+        let mut aggregator = MvccPropertiesAggregator::new();
+        let empty: HashMap<Vec<u8>, Vec<u8>> = HashMap::new();
+        assert!(aggregator.push(&empty).is_err());
+    }
+
+    #[test]
+    fn test_aggregate_properties() {
+        let props = vec![collect_versions(&[("a", 2), ("b", 1)]),
+                         MvccProperties::new(),
+                         collect_versions(&[("c", 3)])];
+        let futures: Vec<_> = props.iter()
+            .map(|p| future::ok(p.encode().into_iter().collect()))
+            .collect();
+        let res = aggregate_properties(futures).wait().unwrap();
+        let expected = MvccProperties::from_iter(props.clone());
+        assert_eq!(res.encode(), expected.encode());
+
+        // The sync version gives the same result.
+        let mut aggregator = MvccPropertiesAggregator::new();
+        for p in &props {
+            aggregator.push(&p.encode()).unwrap();
+        }
+        assert_eq!(aggregator.finish().encode(), expected.encode());
+
+        let none: Vec<future::FutureResult<_, codec::Error>> = vec![];
+        assert_eq!(aggregate_properties(none).wait().unwrap().encode(),
+                   MvccProperties::new().encode());
+        let futures = vec![future::ok(props[0].encode().into_iter().collect()),
+                           future::err(codec::Error::KeyNotFound)];
+        assert!(aggregate_properties(futures).wait().is_err());
+        // Properties failed to decode.
+        assert!(aggregate_properties(vec![future::ok(HashMap::new())]).wait().is_err());
+    }
+
+    #[test]
+    fn test_decode_and_merge() {
+        let a = collect_versions(&[("a", 2), ("b", 1)]);
+        let b = collect_versions(&[("b", 3), ("c", 1)]);
+        let mut expected = a.clone();
+        expected.add(&b);
+        let expected: HashMap<_, _> = expected.encode().into_iter().collect();
+
+        let encoded_a: HashMap<_, _> = a.encode().into_iter().collect();
+        let encoded_b: HashMap<_, _> = b.encode().into_iter().collect();
+        assert_eq!(decode_and_merge(&encoded_a, &encoded_b).unwrap(), expected);
+
+        // Older versions are merged into the current one, other keys are dropped.
+        let mut encoded_a: HashMap<_, _> =
+            a.encode_with_version(super::PROP_VERSION_V1).into_iter().collect();
+        encoded_a.insert(b"tikv.other".to_vec(), b"other".to_vec());
+        assert_eq!(decode_and_merge(&encoded_a, &encoded_b).unwrap(), expected);
+
+        assert!(decode_and_merge(&encoded_a, &HashMap::new()).is_err());
+    }
+
+    #[test]
+    fn test_num_ts_anomalies() {
+        let cases = [("a", 5), ("a", 7), ("a", 6), ("a", 8), ("b", 9), ("b", 1)];
+        let mut collector = UserPropertiesCollector::default();
+        for &(key, ts) in &cases {
+            let k = Key::from_raw(key.as_bytes()).append_ts(ts);
+            let k = keys::data_key(k.encoded());
+            let v = Write::new(WriteType::Put, ts, None).to_bytes();
+            collector.add(&k, &v, DBEntryType::Put, 0, 0);
+        }
+        let props = MvccProperties::decode(&collector.finish()).unwrap();
+        assert_eq!(props.num_rows, 2);
+        assert_eq!(props.num_ts_anomalies, 2);
+    }
+
+    #[test]
+    fn test_num_duplicate_versions() {
+        // The tombstone of a version has the same key and ts, but isn't a duplicate.
+        let cases = [("a", 5, DBEntryType::Put),
+                     ("a", 5, DBEntryType::Put),
+                     ("a", 4, DBEntryType::Put),
+                     ("a", 4, DBEntryType::Delete),
+                     ("b", 4, DBEntryType::Put),
+                     ("b", 4, DBEntryType::Put),
+                     ("b", 4, DBEntryType::Put)];
+        let mut collector = UserPropertiesCollector::default();
+        for &(key, ts, entry_type) in &cases {
+            let k = Key::from_raw(key.as_bytes()).append_ts(ts);
+            let k = keys::data_key(k.encoded());
+            let v = Write::new(WriteType::Put, ts, None).to_bytes();
+            collector.add(&k, &v, entry_type, 0, 0);
+        }
+        let props = MvccProperties::decode(&collector.finish()).unwrap();
+        assert_eq!(props.num_rows, 2);
+        assert_eq!(props.num_duplicate_versions, 3);
+        assert_eq!(props.num_ts_anomalies, 0);
+
+        let mut total = props.clone();
+        total.add(&props);
+        assert_eq!(total.num_duplicate_versions, 6);
+    }
+
+    #[test]
+    fn test_start_ts_range() {
+        // (key, commit ts, start ts), the start ts is always before the commit ts.
+        let cases = [("a", 10, 9), ("a", 8, 2), ("b", 20, 5), ("c", 15, 14)];
+        let mut collector = UserPropertiesCollector::default();
+        for &(key, commit_ts, start_ts) in &cases {
+            let k = keys::data_key(Key::from_raw(key.as_bytes()).append_ts(commit_ts).encoded());
+            let v = Write::new(WriteType::Put, start_ts, None).to_bytes();
+            collector.add(&k, &v, DBEntryType::Put, 0, 0);
+        }
+        // Writes failed to parse are not counted.
+        let k = keys::data_key(Key::from_raw(b"d").append_ts(30).encoded());
+        collector.add(&k, b"bad", DBEntryType::Put, 0, 0);
+        let props = MvccProperties::decode(&collector.finish()).unwrap();
+        assert_eq!((props.min_ts, props.max_ts), (8, 30));
+        assert_eq!((props.min_start_ts, props.max_start_ts), (2, 14));
+
+        let mut total = props.clone();
+        total.add(&collect_versions(&[("e", 1)]));
+        assert_eq!((total.min_start_ts, total.max_start_ts), (0, 14));
+
+        // Writes are not parsed with `FAST_COUNTS_ONLY`.
+        let flags = MVCC_PROPERTIES | FAST_COUNTS_ONLY;
+        let mut collector = UserPropertiesCollector::new(CollectorOptions::from_flags(flags));
+        let k = keys::data_key(Key::from_raw(b"a").append_ts(10).encoded());
+        let v = Write::new(WriteType::Put, 9, None).to_bytes();
+        collector.add(&k, &v, DBEntryType::Put, 0, 0);
+        let props = MvccProperties::decode(&collector.finish()).unwrap();
+        assert_eq!((props.min_start_ts, props.max_start_ts), (u64::MAX, 0));
+        assert!(!props.is_field_valid(PropKey::MinStartTs));
+    }
+
+    #[test]
+    fn test_inlined_values() {
+        let cases = [(WriteType::Put, Some(b"v".to_vec())),
+                     (WriteType::Put, None),
+                     (WriteType::Put, Some(vec![])),
+                     (WriteType::Delete, None),
+                     (WriteType::Lock, Some(b"v".to_vec())),
+                     (WriteType::Put, None),
+                     (WriteType::Put, None)];
+        let mut collector = UserPropertiesCollector::default();
+        for (i, &(write_type, ref short_value)) in cases.iter().enumerate() {
+            let k = Key::from_raw(b"k").append_ts(100 - i as u64);
+            let k = keys::data_key(k.encoded());
+            let v = Write::new(write_type, 1, short_value.clone()).to_bytes();
+            collector.add(&k, &v, DBEntryType::Put, 0, 0);
+        }
+        let props = MvccProperties::decode(&collector.finish()).unwrap();
+        assert_eq!(props.num_puts, 5);
+        assert_eq!(props.num_inlined_values, 2);
+        assert_eq!(props.num_pointer_values, 3);
+
+        let mut total = props.clone();
+        total.add(&props);
+        assert_eq!((total.num_inlined_values, total.num_pointer_values), (4, 6));
+    }
+
+    #[test]
+    fn test_max_version_gap() {
+        let cases = [("a", 100), ("a", 90), ("a", 10), ("b", 200), ("c", 50), ("c", 49)];
+        let mut collector = UserPropertiesCollector::default();
+        for &(key, ts) in &cases {
+            let k = Key::from_raw(key.as_bytes()).append_ts(ts);
+            let k = keys::data_key(k.encoded());
+            let v = Write::new(WriteType::Put, ts, None).to_bytes();
+            collector.add(&k, &v, DBEntryType::Put, 0, 0);
+        }
+        let props = MvccProperties::decode(&collector.finish()).unwrap();
+        // Gaps between rows, e.g. from 10 of a to 200 of b, don't count.
+        assert_eq!(props.max_version_gap, 80);
+
+        let mut total = MvccProperties::with(1, 1, 1, 1, 1, 1);
+        total.max_version_gap = 100;
+        total.add(&props);
+        assert_eq!(total.max_version_gap, 100);
+
+        // Versions out of order are anomalies, not gaps.
+        let mut collector = UserPropertiesCollector::default();
+        for &ts in &[5, 9] {
+            let k = keys::data_key(Key::from_raw(b"d").append_ts(ts).encoded());
+            let v = Write::new(WriteType::Put, ts, None).to_bytes();
+            collector.add(&k, &v, DBEntryType::Put, 0, 0);
+        }
+        let props = MvccProperties::decode(&collector.finish()).unwrap();
+        assert_eq!(props.num_ts_anomalies, 1);
+        assert_eq!(props.max_version_gap, 0);
+    }
+
+    #[test]
+    fn test_fast_counts_only() {
+        let flags = MVCC_PROPERTIES | FAST_COUNTS_ONLY;
+        let mut collector = UserPropertiesCollector::new(CollectorOptions::from_flags(flags));
+        for &(key, ts) in &[("a", 2), ("a", 1), ("b", 3)] {
+            let k = Key::from_raw(key.as_bytes()).append_ts(ts);
+            let k = keys::data_key(k.encoded());
+            collector.add(&k, b"not a write", DBEntryType::Put, 0, 0);
+        }
+        let res = UserProperties::decode(&collector.finish()).unwrap();
+        assert_eq!(res.num_errors, 0);
+        let props = res.mvcc;
+        assert!(!props.puts_valid);
+        assert_eq!(props.num_puts, 0);
+        assert_eq!(props.num_versions, 3);
+        assert_eq!(props.num_rows, 2);
+        assert_eq!((props.min_ts, props.max_ts), (1, 3));
+
+        // Aggregated properties are invalid if any of them is.
+        let mut total = collect_versions(&[("c", 1)]);
+        assert!(total.puts_valid);
+        total.add(&props);
+        assert!(!total.puts_valid);
+
+        // Properties of older SSTs are always parsed.
+        let props = collect_versions(&[("c", 1)]);
+        let mut encoded = props.encode_with_version(super::PROP_VERSION_V2);
+        encoded.remove(PropKey::PutsValid.as_bytes());
+        assert!(MvccProperties::decode(&encoded).unwrap().puts_valid);
+    }
+
+    #[test]
+    fn test_sample_rate() {
+        let mut opts = CollectorOptions::default();
+        opts.sample_rate = 4;
+        let mut collector = UserPropertiesCollector::new(opts);
+        // Versions 0, 4 and 8 are sampled.
+        for i in 0..12 {
+            let write_type = if i == 4 { WriteType::Delete } else { WriteType::Put };
+            let k = Key::from_raw(format!("k{:02}", i).as_bytes()).append_ts(1);
+            let k = keys::data_key(k.encoded());
+            let v = if i % 4 == 0 {
+                Write::new(write_type, 1, None).to_bytes()
+            } else {
+                // Not parsed, so no errors.
+                b"not a write".to_vec()
+            };
+            collector.add(&k, &v, DBEntryType::Put, 0, 0);
+        }
+        let res = UserProperties::decode(&collector.finish()).unwrap();
+        assert_eq!(res.num_errors, 0);
+        assert_eq!(res.mvcc.num_versions, 12);
+        assert_eq!(res.mvcc.num_rows, 12);
+        assert_eq!(res.mvcc.num_puts, 8);
+        assert_eq!(res.mvcc.num_deletes, 4);
+        assert_eq!(res.mvcc.num_fully_deleted_rows, 1);
+
+        // Scaled counters never exceed the number of versions.
+        let mut opts = CollectorOptions::default();
+        opts.sample_rate = 4;
+        let mut collector = UserPropertiesCollector::new(opts);
+        for i in 0..5 {
+            let k = Key::from_raw(format!("k{:02}", i).as_bytes()).append_ts(1);
+            let k = keys::data_key(k.encoded());
+            let v = Write::new(WriteType::Put, 1, None).to_bytes();
+            collector.add(&k, &v, DBEntryType::Put, 0, 0);
+        }
+        let props = MvccProperties::decode(&collector.finish()).unwrap();
+        assert_eq!(props.num_puts, 5);
+    }
+
+    #[test]
+    fn test_prefix_bloom() {
+        let collect = |prefixes: &[String]| {
+            let mut opts = CollectorOptions::from_flags(MVCC_PROPERTIES | PREFIX_BLOOM);
+            opts.bloom_prefix_len = 8;
+            let mut collector = UserPropertiesCollector::new(opts);
+            for p in prefixes {
+                let k = Key::from_raw(format!("{}_suffix", p).as_bytes()).append_ts(1);
+                let k = keys::data_key(k.encoded());
+                let v = Write::new(WriteType::Put, 1, None).to_bytes();
+                collector.add(&k, &v, DBEntryType::Put, 0, 0);
+            }
+            MvccProperties::decode(&collector.finish()).unwrap()
+        };
+
+        let prefixes: Vec<_> = (0..1000).map(|i| format!("p{:07}", i)).collect();
+        let mut props = collect(&prefixes[..500]);
+        props.add(&collect(&prefixes[500..]));
+        for p in &prefixes {
+            assert!(props.may_contain_prefix(p.as_bytes()));
+            assert!(props.may_contain_prefix(format!("{}_suffix", p).as_bytes()));
+        }
+        let false_positives = (0..10000)
+            .filter(|i| props.may_contain_prefix(format!("q{:07}", i).as_bytes()))
+            .count();
+        assert!(false_positives < 10000 / 100, "{}", false_positives);
+
+        // Shorter prefixes can't be checked.
+        assert!(props.may_contain_prefix(b"q"));
+        // Without a filter in any operand, every prefix may exist.
+        props.add(&collect_versions(&[("a", 1)]));
+        assert!(props.prefix_bloom.is_empty());
+        assert!(props.may_contain_prefix(b"q0000000"));
+    }
+
+    #[test]
+    fn test_avg_per_row() {
+        let props = MvccProperties::new();
+        assert_eq!(props.avg_versions_per_row(), 0.0);
+        assert_eq!(props.avg_puts_per_row(), 0.0);
+
+        // Rows are not collected without `MVCC_PROPERTIES`.
+        let mut props = props;
+        props.num_versions = 3;
+        props.num_puts = 3;
+        assert_eq!(props.avg_versions_per_row(), 0.0);
+        assert_eq!(props.avg_puts_per_row(), 0.0);
+
+        let props = collect_versions(&[("a", 3), ("b", 1)]);
+        assert_eq!(props.avg_versions_per_row(), 2.0);
+        assert_eq!(props.avg_puts_per_row(), 2.0);
+    }
+
+    #[test]
+    fn test_display() {
+        let props = collect_versions(&[("a", 2), ("b", 1)]);
+        assert_eq!(format!("{}", props),
+                   "rows=2 puts=3 deletes=0 versions=3 ts=[0,1] max_row_versions=2");
+    }
+
+    #[test]
+    fn test_diff() {
+        let older = collect_versions(&[("a", 2), ("b", 1)]);
+        let newer = collect_versions(&[("a", 5), ("b", 1), ("c", 1)]);
+        let expected = MvccPropertiesDiff {
+            num_rows: 1,
+            num_puts: 4,
+            num_versions: 4,
+            ts_range_expanded: true,
+        };
+        assert_eq!(newer.diff(&older), expected);
+
+        // Shrinks, e.g. after GC.
+        let expected = MvccPropertiesDiff {
+            num_rows: -1,
+            num_puts: -4,
+            num_versions: -4,
+            ts_range_expanded: false,
+        };
+        assert_eq!(older.diff(&newer), expected);
+        assert_eq!(older.diff(&older), MvccPropertiesDiff::default());
+
+        let mut huge = MvccProperties::new();
+        huge.num_rows = u64::MAX;
+        assert_eq!(huge.diff(&MvccProperties::new()).num_rows, i64::MAX);
+        assert_eq!(MvccProperties::new().diff(&huge).num_rows, -i64::MAX);
+    }
+
+    #[test]
+    fn test_prop_version_v2() {
+        let mut props = collect_versions(&[("a", 2), ("b", 1)]);
+        props.max_ts = u64::MAX;
+        let v1 = props.encode_with_version(super::PROP_VERSION_V1);
+        let v2 = props.encode_with_version(super::PROP_VERSION_V2);
+        assert_eq!(v2.decode_u64(super::PROP_VERSION).unwrap(), 2);
+        assert_eq!(v2[PropKey::NumRows.as_bytes()], vec![2]);
+        assert!(v2.values().map(|v| v.len()).sum::<usize>() <
+                v1.values().map(|v| v.len()).sum::<usize>());
+        for encoded in &[v1, v2] {
+            let decoded = MvccProperties::decode(encoded).unwrap();
+            assert_eq!(decoded.encode(), props.encode());
+        }
+    }
+
+    #[test]
+    fn test_slice_properties() {
+        let props = collect_versions(&[("a", 2), ("b", 1)]);
+        let pairs: Vec<_> = props.encode().into_iter().collect();
+        let decoded = MvccProperties::decode(&SliceProperties(&pairs)).unwrap();
+        assert_eq!(decoded.encode(), props.encode());
+
+        let pairs = vec![(b"tikv.num".to_vec(), super::encode_u64(3))];
+        let props = SliceProperties(&pairs);
+        assert_eq!(props.decode_u64("tikv.num").unwrap(), 3);
+        assert_eq!(props.decode_u64_or("tikv.not_exist", 7).unwrap(), 7);
+        assert!(props.decode_bytes("tikv.not_exist").is_err());
+    }
+
+    #[test]
+    fn test_versions_histogram() {
+        let props = collect_versions(&[("a", 1), ("b", 4), ("c", 5), ("d", 64), ("e", 65),
+                                       ("f", 2), ("g", 1)]);
+        assert_eq!(props.versions_histogram, [2, 2, 1, 1, 1]);
+        let decoded = MvccProperties::decode(&props.encode()).unwrap();
+        assert_eq!(decoded.versions_histogram, [2, 2, 1, 1, 1]);
+        assert!(MvccProperties::decode_versions_histogram(b"short").is_err());
+
+        let mut total = props.clone();
+        total.add(&collect_versions(&[("h", 3)]));
+        assert_eq!(total.versions_histogram, [2, 3, 1, 1, 1]);
+
+        // The row straddling both SSTs has 3 versions.
+        let mut stitched = collect_versions(&[("a", 1), ("b", 2)]);
+        stitched.add_adjacent(&collect_versions(&[("b", 1), ("c", 1)]));
+        assert_eq!(stitched.versions_histogram, [2, 1, 0, 0, 0]);
+
+        // Older SSTs don't have the histogram.
+        let mut encoded = props.encode_with_version(super::PROP_VERSION_V2);
+        encoded.remove(super::PROP_VERSIONS_HISTOGRAM.as_bytes());
+        let decoded = MvccProperties::decode(&encoded).unwrap();
+        assert_eq!(decoded.versions_histogram, [0; 5]);
+    }
+
+    #[test]
+    fn test_covers_range() {
+        let props = collect_versions(&[("b", 1), ("d", 1)]);
+        let key = |k: &[u8]| keys::data_key(Key::from_raw(k).encoded());
+        let (a, b, c, d, e) = (key(b"a"), key(b"b"), key(b"c"), key(b"d"), key(b"e"));
+        // Disjoint.
+        assert!(!props.covers_range(&a, &b));
+        assert!(!props.covers_range(&e, b""));
+        // Overlapping.
+        assert!(props.covers_range(&a, &c));
+        assert!(props.covers_range(&c, b""));
+        assert!(props.covers_range(b"", &c));
+        assert!(props.covers_range(&d, &e));
+        // Contained in either way.
+        assert!(props.covers_range(&c, &d));
+        assert!(props.covers_range(b"", b""));
+
+        // Empty properties have no keys.
+        assert!(!MvccProperties::new().covers_range(b"", b""));
+        // Properties without the key range may cover any range.
+        let mut props = props;
+        props.smallest_key.clear();
+        props.largest_key.clear();
+        assert!(props.covers_range(&a, &b));
+    }
+
+    #[test]
+    fn test_default_cf_properties() {
+        let mut opts = CollectorOptions::default();
+        opts.cf_mode = CfMode::Default;
+        let mut collector = UserPropertiesCollector::new(opts);
+        let entries = [(b"a", 1, DBEntryType::Put),
+                       (b"b", 10, DBEntryType::Put),
+                       (b"c", 1000, DBEntryType::Put),
+                       (b"d", 5000, DBEntryType::Delete)];
+        for &(k, size, entry_type) in &entries {
+            let k = keys::data_key(Key::from_raw(k).append_ts(1).encoded());
+            collector.add(&k, &vec![0; size], entry_type, 0, 0);
+        }
+
+        let props = UserProperties::decode(&collector.finish()).unwrap();
+        assert_eq!(props.num_errors, 0);
+        assert!(props.mvcc.is_empty());
+        assert_eq!(props.mvcc.max_value_size, 1000);
+        assert!(props.lock.is_none());
+        let default_cf = props.default_cf.unwrap();
+        assert_eq!(default_cf.total_default_size, 1011);
+
+        let mut total = default_cf.clone();
+        total.add(&default_cf);
+        assert_eq!(total.total_default_size, 2022);
+    }
+
+    #[test]
+    fn test_empty_key_and_value() {
+        let key = keys::data_key(Key::from_raw(b"k").append_ts(1).encoded());
+        let value = Write::new(WriteType::Put, 0, None).to_bytes();
+        for &cf_mode in &[CfMode::Write, CfMode::Lock, CfMode::Default] {
+            let mut opts = CollectorOptions::from_flags(MVCC_PROPERTIES | FAST_COUNTS_ONLY);
+            opts.cf_mode = cf_mode;
+            let mut collector = UserPropertiesCollector::new(opts);
+            collector.add(b"", &value, DBEntryType::Put, 0, 0);
+            collector.add(b"", b"", DBEntryType::Delete, 0, 0);
+            collector.add(&key, b"", DBEntryType::Put, 0, 0);
+            // Empty values of deletes are fine.
+            collector.add(&key, b"", DBEntryType::Delete, 0, 0);
+
+            let props = UserProperties::decode(&collector.finish()).unwrap();
+            assert_eq!(props.num_errors, 3, "{:?}", cf_mode);
+            assert_eq!(props.num_non_data_keys, 0);
+            assert_eq!(props.mvcc.num_versions, 0);
+            if let Some(lock) = props.lock {
+                assert_eq!(lock.num_locks, 0);
+            }
+            if let Some(default_cf) = props.default_cf {
+                assert_eq!(default_cf.total_default_size, 0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_collector_reset() {
+        let ssts: [&[(&str, u64)]; 2] = [&[("a", 3), ("b", 1)], &[("c", 2)]];
+        let flags = MVCC_PROPERTIES | KEY_CARDINALITY | PREFIX_BLOOM;
+        let mut factory = UserPropertiesCollectorFactory::with_flags(flags);
+        let mut reused = UserPropertiesCollector::new(CollectorOptions::from_flags(flags));
+        reused.props.cf_id = Some(3);
+        for rows in &ssts {
+            reused.reset();
+            let mut fresh = factory.create_table_properties_collector(3);
+            for &(key, versions) in rows.iter() {
+                for ts in 0..versions {
+                    let k = Key::from_raw(key.as_bytes()).append_ts(ts);
+                    let k = keys::data_key(k.encoded());
+                    let v = Write::new(WriteType::Put, ts, None).to_bytes();
+                    reused.add(&k, &v, DBEntryType::Put, 0, 0);
+                    fresh.add(&k, &v, DBEntryType::Put, 0, 0);
+                }
+            }
+            let res = reused.finish();
+            assert_eq!(res, fresh.finish());
+            let props = UserProperties::decode(&res).unwrap();
+            assert_eq!(props.cf_id, Some(3));
+            assert_eq!(props.mvcc.num_rows, rows.len() as u64);
+            assert_eq!(props.mvcc.num_versions,
+                       rows.iter().map(|&(_, versions)| versions).sum::<u64>());
+            let smallest = Key::from_raw(rows[0].0.as_bytes());
+            assert_eq!(props.mvcc.smallest_key, keys::data_key(smallest.encoded()));
+        }
+    }
+
+    #[test]
+    fn test_collected_flags() {
+        for &flags in &[PropertiesFlags::empty(),
+                        MVCC_PROPERTIES,
+                        DELETE_RATIO,
+                        MVCC_PROPERTIES | KEY_CARDINALITY | PREFIX_BLOOM] {
+            let mut collector = UserPropertiesCollector::new(CollectorOptions::from_flags(flags));
+            let props = UserProperties::decode(&collector.finish()).unwrap();
+            assert_eq!(props.mvcc.collected_flags, Some(flags));
+        }
+
+        // Properties written before the flags are recorded.
+        let props = MvccProperties::decode(&MvccProperties::new().encode()).unwrap();
+        assert_eq!(props.collected_flags, None);
+
+        // Unknown bits from a newer version are kept.
+        let mut props = MvccProperties::new();
+        props.collected_flags = Some(PropertiesFlags::from_bits(1 << 31));
+        let props = MvccProperties::decode(&props.encode()).unwrap();
+        assert_eq!(props.collected_flags.unwrap().bits(), 1 << 31);
+
+        let mut encoded = MvccProperties::new().encode_with_version(super::PROP_VERSION_V2);
+        encoded.insert(super::PROP_COLLECTED_FLAGS.as_bytes().to_owned(),
+                       super::encode_u64(u64::MAX));
+        assert!(MvccProperties::decode(&encoded).is_err());
+    }
+
+    #[test]
+    fn test_collect_without_mvcc_flags() {
+        let collect = |flags| {
+            let mut collector = UserPropertiesCollector::new(CollectorOptions::from_flags(flags));
+            for ts in 1..4 {
+                let k = keys::data_key(Key::from_raw(b"k").append_ts(ts).encoded());
+                // Parsing the garbage fails, if it's parsed.
+                collector.add(&k, b"garbage", DBEntryType::Put, 0, 0);
+            }
+            UserProperties::decode(&collector.finish()).unwrap()
+        };
+
+        for &flags in &[PropertiesFlags::empty(), KEY_CARDINALITY, STRICT_ENTRY_TYPES | TIMING] {
+            let props = collect(flags);
+            assert_eq!(props.num_errors, 0);
+            assert!(!props.mvcc.puts_valid);
+            assert!(!props.mvcc.is_field_valid(PropKey::NumDeletes));
+            // The cheap counters are still collected.
+            assert_eq!(props.mvcc.num_versions, 3);
+            assert_eq!(props.entry_count, 3);
+        }
+        for &flags in &[MVCC_PROPERTIES, DELETE_RATIO] {
+            let props = collect(flags);
+            assert_eq!(props.num_errors, 3);
+            assert!(props.mvcc.puts_valid);
+        }
+    }
+
+    #[test]
+    fn test_prop_keys() {
+        let mut props = MvccProperties::new();
+        for (i, &k) in super::PROP_KEYS.iter().enumerate() {
+            // Different from the defaults, so decoding each key is checked.
+            props.set(k, if k == PropKey::PutsValid { 0 } else { i as u64 + 2 });
+        }
+        let encoded = props.encode();
+        let keys: Vec<_> = encoded.keys().filter_map(|k| PropKey::from_bytes(k)).collect();
+        assert_eq!(keys.len(), super::PROP_KEYS.len());
+        for k in super::PROP_KEYS {
+            assert!(keys.contains(k), "{:?}", k);
+            assert_eq!(PropKey::from_bytes(k.as_bytes()), Some(*k));
+        }
+        assert_eq!(PropKey::from_bytes(b"tikv.not_exist"), None);
+
+        let decoded = MvccProperties::decode(&encoded).unwrap();
+        for &k in super::PROP_KEYS {
+            assert_eq!(decoded.get(k), props.get(k), "{:?}", k);
+
+            let mut encoded = props.encode_with_version(super::PROP_VERSION_V2);
+            encoded.remove(k.as_bytes());
+            match k.default_value() {
+                Some(v) => assert_eq!(MvccProperties::decode(&encoded).unwrap().get(k), v),
+                None => assert!(MvccProperties::decode(&encoded).is_err()),
+            }
+        }
+    }
+
+    #[test]
+    fn test_noop_collector() {
+        let data_key = keys::data_key(Key::from_raw(b"k").append_ts(1).encoded());
+        let entries = [(data_key.clone(), Write::new(WriteType::Put, 1, None).to_bytes()),
+                       (data_key, Lock::new(LockType::Put, b"k".to_vec(), 1, 0, None).to_bytes()),
+                       (keys::raft_log_key(1, 1), b"entry".to_vec()),
+                       (b"".to_vec(), b"".to_vec())];
+
+        let mut opts = CollectorOptions::default();
+        opts.cf_mode = CfMode::Raft;
+        let mut factory = UserPropertiesCollectorFactory::new(opts);
+        let mut collector = factory.create_table_properties_collector(0);
+        for &(ref k, ref v) in &entries {
+            collector.add(k, v, DBEntryType::Put, 0, 0);
+        }
+        assert!(collector.finish().is_empty());
+
+        // The lock CF has lock properties to collect.
+        let mut opts = CollectorOptions::default();
+        opts.cf_mode = CfMode::Lock;
+        let mut factory = UserPropertiesCollectorFactory::new(opts);
+        let mut collector = factory.create_table_properties_collector(0);
+        for &(ref k, ref v) in &entries {
+            collector.add(k, v, DBEntryType::Put, 0, 0);
+        }
+        let props = UserProperties::decode(&collector.finish()).unwrap();
+        assert_eq!(props.lock.unwrap().num_locks, 1);
+        // The write is malformed as a lock, and is counted with the empty key.
+        assert_eq!(props.num_errors, 2);
+    }
+
+    #[test]
+    fn test_entry_count_and_timing() {
+        for &timing in &[false, true] {
+            let flags = if timing { MVCC_PROPERTIES | TIMING } else { MVCC_PROPERTIES };
+            let mut collector = UserPropertiesCollector::new(CollectorOptions::from_flags(flags));
+            for ts in 0..10 {
+                let k = keys::data_key(Key::from_raw(b"k").append_ts(ts).encoded());
+                let v = Write::new(WriteType::Put, ts, None).to_bytes();
+                collector.add(&k, &v, DBEntryType::Put, 0, 0);
+            }
+            // Entries which are not collected are counted too.
+            collector.add(b"", b"", DBEntryType::Put, 0, 0);
+            collector.add(b"k", b"", DBEntryType::Delete, 0, 0);
+
+            let props = UserProperties::decode(&collector.finish()).unwrap();
+            assert_eq!(props.entry_count, 12);
+            assert_eq!(props.collect_nanos.is_some(), timing);
+        }
+
+        // Properties written before the entry count is recorded.
+        let mut encoded = UserProperties::new().encode();
+        encoded.remove(super::PROP_ENTRY_COUNT.as_bytes());
+        let props = UserProperties::decode(&encoded).unwrap();
+        assert_eq!(props.entry_count, 0);
+        assert_eq!(props.collect_nanos, None);
+    }
+
+    #[test]
+    fn test_encoded_bytes() {
+        // Locks down the encoding, properties already written must stay readable.
+        assert_eq!(super::encode_u64(0x0102030405060708), vec![1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(super::encode_var_u64(1), vec![1]);
+        assert_eq!(super::encode_var_u64(300), vec![0xac, 0x02]);
+
+        let mut props = MvccProperties::new();
+        props.num_rows = 0x0102;
+        let v1 = props.encode_with_version(super::PROP_VERSION_V1);
+        assert_eq!(v1[super::PROP_VERSION.as_bytes()], vec![0, 0, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(v1[PropKey::NumRows.as_bytes()], vec![0, 0, 0, 0, 0, 0, 1, 2]);
+        let v2 = props.encode_with_version(super::PROP_VERSION_V2);
+        assert_eq!(v2[super::PROP_VERSION.as_bytes()], vec![0, 0, 0, 0, 0, 0, 0, 2]);
+        assert_eq!(v2[PropKey::NumRows.as_bytes()], vec![0x82, 0x02]);
+
+        let mut encoded = HashMap::new();
+        encoded.insert(b"tikv.num".to_vec(), vec![0, 0, 0, 0, 0, 0, 1, 0]);
+        assert_eq!(encoded.decode_u64("tikv.num").unwrap(), 256);
+    }
+
+    #[test]
+    fn test_gc_score() {
+        let mut props = MvccProperties::new();
+        assert_eq!(props.gc_score(), 0);
+
+        // Single-version rows, nothing to GC.
+        props.num_rows = 10;
+        props.num_versions = 10;
+        props.num_puts = 10;
+        props.max_row_versions = 1;
+        assert_eq!(props.gc_score(), 0);
+
+        // All deletes, every row can be removed.
+        props.num_puts = 0;
+        props.num_deletes = 10;
+        assert_eq!(props.gc_score(), 60);
+        // Unless deletes are not collected.
+        props.puts_valid = false;
+        assert_eq!(props.gc_score(), 0);
+        props.puts_valid = true;
+
+        // A single hot row of deletes is the most worth GC.
+        props.num_rows = 1;
+        props.num_versions = 1000;
+        props.num_deletes = 1000;
+        props.max_row_versions = 1000;
+        assert_eq!(props.gc_score(), 100);
+
+        // Old versions without deletes.
+        props.num_rows = 10;
+        props.num_versions = 20;
+        props.num_puts = 20;
+        props.num_deletes = 0;
+        props.max_row_versions = 2;
+        assert_eq!(props.gc_score(), 15);
+
+        // Only counters are collected.
+        props.num_rows = 0;
+        props.max_row_versions = 0;
+        props.num_puts = 10;
+        props.num_deletes = 10;
+        assert_eq!(props.gc_score(), 30);
+    }
+
+    #[test]
+    fn test_min_row_latest_ts() {
+        // (row, ts), versions of a row are in descending ts order.
+        let cases = [("a", 9), ("a", 1), ("b", 5), ("b", 4), ("c", 7), ("d", 6), ("d", 8)];
+        let mut collector = UserPropertiesCollector::default();
+        for &(key, ts) in &cases {
+            let k = keys::data_key(Key::from_raw(key.as_bytes()).append_ts(ts).encoded());
+            let v = Write::new(WriteType::Put, ts, None).to_bytes();
+            collector.add(&k, &v, DBEntryType::Put, 0, 0);
+        }
+        let props = MvccProperties::decode(&collector.finish()).unwrap();
+        // Row b is updated the longest ago, the anomaly of row d is tolerated.
+        assert_eq!(props.min_row_latest_ts, 5);
+        assert_eq!(props.num_ts_anomalies, 1);
+
+        // The last row is counted too.
+        let props = collect_versions(&[("a", 3)]);
+        assert_eq!(props.min_row_latest_ts, 2);
+
+        let mut total = MvccProperties::new();
+        assert_eq!(total.min_row_latest_ts, u64::MAX);
+        total.add(&props);
+        assert_eq!(total.min_row_latest_ts, 2);
+    }
+
+    #[test]
+    fn test_num_rollbacks() {
+        let cases = [("a", 5, WriteType::Rollback),
+                     ("a", 4, WriteType::Put),
+                     ("a", 3, WriteType::Rollback),
+                     ("b", 6, WriteType::Lock),
+                     ("b", 2, WriteType::Delete),
+                     ("c", 7, WriteType::Rollback)];
+        let mut collector = UserPropertiesCollector::default();
+        for &(key, ts, write_type) in &cases {
+            let k = keys::data_key(Key::from_raw(key.as_bytes()).append_ts(ts).encoded());
+            let v = Write::new(write_type, ts, None).to_bytes();
+            collector.add(&k, &v, DBEntryType::Put, 0, 0);
+        }
+        let props = MvccProperties::decode(&collector.finish()).unwrap();
+        assert_eq!(props.num_rollbacks, 3);
+        assert_eq!(props.num_puts, 1);
+        assert_eq!(props.num_deletes, 1);
+        assert_eq!(props.num_versions, 6);
+
+        let mut total = props.clone();
+        total.add(&props);
+        assert_eq!(total.num_rollbacks, 6);
+    }
+
+    #[test]
+    fn test_try_decode_u64() {
+        fn check<T: DecodeU64>(props: &T) {
+            assert_eq!(props.try_decode_u64("num").unwrap(), Some(3));
+            assert_eq!(props.try_decode_u64("absent").unwrap(), None);
+            assert!(props.try_decode_u64("malformed").is_err());
+        }
+
+        let mut props = HashMap::new();
+        props.insert(b"num".to_vec(), super::encode_u64(3));
+        props.insert(b"malformed".to_vec(), vec![1, 2, 3]);
+        check(&props);
+        check(&props.into_iter().collect::<BTreeMap<_, _>>());
+    }
+
+    #[test]
+    fn test_estimate_versions_in_window() {
+        let props = MvccProperties::with(100, 199, 10, 50, 50, 5);
+        // Partially overlapping windows.
+        assert_eq!(props.estimate_versions_in_window(100, 149), 25);
+        assert_eq!(props.estimate_versions_in_window(150, 1000), 25);
+        assert_eq!(props.estimate_versions_in_window(120, 139), 10);
+        assert_eq!(props.estimate_versions_in_window(0, 100), 1);
+        assert_eq!(props.estimate_versions_in_window(199, 199), 1);
+        // Windows outside the range.
+        assert_eq!(props.estimate_versions_in_window(0, 99), 0);
+        assert_eq!(props.estimate_versions_in_window(200, u64::MAX), 0);
+        assert_eq!(props.estimate_versions_in_window(150, 120), 0);
+        // Windows covering the range.
+        assert_eq!(props.estimate_versions_in_window(100, 199), 50);
+        assert_eq!(props.estimate_versions_in_window(0, u64::MAX), 50);
+
+        let props = MvccProperties::with(0, u64::MAX, 10, 50, 50, 5);
+        assert_eq!(props.estimate_versions_in_window(0, u64::MAX), 50);
+        assert_eq!(props.estimate_versions_in_window(0, u64::MAX / 2), 25);
+        let props = MvccProperties::with(7, 7, 1, 3, 3, 3);
+        assert_eq!(props.estimate_versions_in_window(7, 7), 3);
+        assert_eq!(MvccProperties::new().estimate_versions_in_window(0, u64::MAX), 0);
+    }
+
+    #[test]
+    fn test_approximate_size() {
+        assert_eq!(MvccProperties::new().approximate_size(100), 0);
+        let props = MvccProperties::with(1, 10, 3, 5, 5, 2);
+        assert_eq!(props.approximate_size(0), 0);
+        assert_eq!(props.approximate_size(100), 300);
+        assert_eq!(props.approximate_size(u64::MAX / 3), u64::MAX / 3 * 3);
+        assert_eq!(props.approximate_size(u64::MAX / 2), u64::MAX);
+        assert_eq!(props.approximate_size(u64::MAX), u64::MAX);
+    }
+
+    #[test]
+    fn test_gc_eligible_below() {
+        let can_skip = |props: &MvccProperties, safe_point: u64| props.can_skip_gc(safe_point);
+
+        assert_eq!(MvccProperties::new().gc_eligible_below(), 0);
+
+        let props = MvccProperties::with(100, 200, 2, 4, 4, 3);
+        assert_eq!(props.gc_eligible_below(), 200);
+        // The SST is entirely above the safe point.
+        assert!(can_skip(&props, 50));
+        assert!(can_skip(&props, 99));
+        // The SST is entirely below the safe point.
+        assert!(!can_skip(&props, 300));
+        assert!(!can_skip(&props, 100));
+
+        // The latest version of single-version rows is never collected.
+        let props = MvccProperties::with(100, 200, 4, 4, 4, 1);
+        assert_eq!(props.gc_eligible_below(), 0);
+        assert!(can_skip(&props, 300));
+
+        // Deletes are collected even if they're the latest version.
+        let mut props = MvccProperties::with(100, 200, 4, 3, 4, 1);
+        props.num_deletes = 1;
+        assert_eq!(props.gc_eligible_below(), 201);
+        assert!(!can_skip(&props, 300));
+        let mut props = MvccProperties::with(100, 100, 1, 0, 1, 1);
+        props.deletes_seen = true;
+        assert!(!can_skip(&props, 100));
+        assert!(can_skip(&props, 99));
+        props.max_ts = u64::MAX;
+        assert_eq!(props.gc_eligible_below(), u64::MAX);
+
+        // Lock and rollback records are collected even if they're the latest
+        // version, and can't be ruled out if writes are not parsed.
+        let mut props = MvccProperties::with(100, 200, 4, 0, 4, 1);
+        props.num_rollbacks = 4;
+        props.num_non_data_versions = 4;
+        assert_eq!(props.gc_eligible_below(), 201);
+        assert!(!can_skip(&props, 300));
+        assert!(can_skip(&props, 99));
+        let mut props = MvccProperties::with(100, 200, 4, 0, 4, 1);
+        props.puts_valid = false;
+        assert_eq!(props.gc_eligible_below(), 201);
+        assert!(!can_skip(&props, 300));
+
+        // `max_row_versions` and the ts range are unknown without the full MVCC
+        // properties.
+        let mut props = MvccProperties::with(100, 200, 4, 4, 4, 1);
+        props.collected_flags = Some(DELETE_RATIO);
+        assert_eq!(props.gc_eligible_below(), u64::MAX);
+        assert!(!can_skip(&props, 300));
+        assert!(!can_skip(&props, 50));
+    }
+
+    #[test]
+    fn test_ts_range_not_collected() {
+        let opts = CollectorOptions::from_flags(DELETE_RATIO);
+        let mut collector = UserPropertiesCollector::new(opts);
+        for &(k, ts) in &[("a", 20), ("a", 10), ("b", 10)] {
+            let k = keys::data_key(Key::from_raw(k.as_bytes()).append_ts(ts).encoded());
+            let v = Write::new(WriteType::Delete, ts, None).to_bytes();
+            collector.add(&k, &v, DBEntryType::Put, 0, 0);
+        }
+        let props = UserProperties::decode(&collector.finish()).unwrap().mvcc;
+        assert_eq!((props.min_ts, props.max_ts), (u64::MAX, 0));
+        assert_eq!(props.num_deletes, 3);
+
+        props.validate().unwrap();
+        assert_eq!(props.ts_range(), None);
+        // It may have versions of any ts.
+        assert!(props.overlaps_ts(0));
+        let opts = GetPropertiesOptions { max_ts: Some(0) };
+        assert_eq!(filter_properties(&[props.clone()], &opts).len(), 1);
+        assert_eq!(props.estimate_versions_in_window(0, 5), 3);
+        // Deletes may be collected by GC.
+        assert_eq!(props.gc_eligible_below(), u64::MAX);
+        assert!(!props.can_skip_gc(0));
+        assert!(!props.can_skip_gc(100));
+        assert!(!props.is_cold(100, 0));
+
+        // The aggregate doesn't have the range either.
+        let mut total = collect_versions(&[("c", 1)]);
+        total.add(&props);
+        assert_eq!(total.ts_range(), None);
+        assert!(total.overlaps_ts(0));
+        assert!(!total.can_skip_gc(100));
+    }
+
+    #[test]
+    fn test_decode_string_keyed_props() {
+        let props = collect_versions(&[("a", 3), ("b", 1)]);
+        let encoded: HashMap<String, Vec<u8>> = props.encode()
+            .into_iter()
+            .map(|(k, v)| (String::from_utf8(k).unwrap(), v))
+            .collect();
+        let decoded = MvccProperties::decode(&encoded).unwrap();
+        assert_eq!(decoded.num_rows, 2);
+        assert_eq!(decoded.num_versions, 4);
+        assert_eq!(decoded.smallest_key, props.smallest_key);
+        assert_eq!(decoded.largest_key, props.largest_key);
+        assert_eq!(decoded.versions_histogram, props.versions_histogram);
+
+        let empty: HashMap<String, Vec<u8>> = HashMap::new();
+        assert!(MvccProperties::decode(&empty).is_err());
+    }
+
+    #[test]
+    fn test_num_single_version_rows() {
+        assert_eq!(collect_versions(&[]).num_single_version_rows, 0);
+        assert_eq!(collect_versions(&[("a", 1)]).num_single_version_rows, 1);
+        assert_eq!(collect_versions(&[("a", 2)]).num_single_version_rows, 0);
+        // The last row is only finished in `finish`.
+        let props = collect_versions(&[("a", 1), ("b", 3), ("c", 1)]);
+        assert_eq!(props.num_single_version_rows, 2);
+        let props = collect_versions(&[("a", 1), ("b", 1), ("c", 2)]);
+        assert_eq!(props.num_single_version_rows, 2);
+
+        // The row straddling adjacent SSTs has two versions.
+        let mut total = collect_versions(&[("a", 1), ("b", 1)]);
+        total.add_adjacent(&collect_versions(&[("b", 1), ("c", 1)]));
+        assert_eq!(total.num_rows, 3);
+        assert_eq!(total.num_single_version_rows, 2);
+        let mut total = collect_versions(&[("a", 1), ("b", 2)]);
+        total.add_adjacent(&collect_versions(&[("b", 1), ("c", 1)]));
+        assert_eq!(total.num_single_version_rows, 2);
+    }
+
+    #[test]
+    fn test_avg_key_len() {
+        assert_eq!(MvccProperties::new().avg_key_len(), 0.0);
+        let props = collect_versions(&[]);
+        assert_eq!(props.total_row_key_len, 0);
+        assert_eq!(props.avg_key_len(), 0.0);
+
+        // Keys are counted once per row, and longer than an encoding group.
+        let props = collect_versions(&[("a", 3), ("bcd", 1), ("0123456789ab", 2)]);
+        assert_eq!(props.total_row_key_len, 16);
+        assert!((props.avg_key_len() - 16.0 / 3.0).abs() < 1e-9);
+
+        let mut total = props.clone();
+        total.add(&collect_versions(&[("efgh", 1)]));
+        assert_eq!(total.total_row_key_len, 20);
+        assert_eq!(total.avg_key_len(), 5.0);
+
+        // The row straddling adjacent SSTs is counted once.
+        let mut total = collect_versions(&[("a", 1), ("bcd", 1)]);
+        total.add_adjacent(&collect_versions(&[("bcd", 1), ("ef", 1)]));
+        assert_eq!(total.num_rows, 3);
+        assert_eq!(total.total_row_key_len, 6);
+    }
+
+    #[test]
+    fn test_num_data_versions() {
+        let cases = [("a", 5, WriteType::Rollback),
+                     ("a", 4, WriteType::Put),
+                     ("a", 3, WriteType::Lock),
+                     ("b", 6, WriteType::Lock),
+                     ("c", 2, WriteType::Delete),
+                     ("c", 1, WriteType::Put)];
+        let mut collector = UserPropertiesCollector::default();
+        for &(key, ts, write_type) in &cases {
+            let k = keys::data_key(Key::from_raw(key.as_bytes()).append_ts(ts).encoded());
+            let v = Write::new(write_type, ts, None).to_bytes();
+            collector.add(&k, &v, DBEntryType::Put, 0, 0);
+        }
+        let props = MvccProperties::decode(&collector.finish()).unwrap();
+        // Rows and versions still count all write records.
+        assert_eq!(props.num_rows, 3);
+        assert_eq!(props.num_versions, 6);
+        assert_eq!(props.num_non_data_versions, 3);
+        assert_eq!(props.num_data_versions(), 3);
+
+        let mut total = props.clone();
+        total.add(&props);
+        assert_eq!(total.num_non_data_versions, 6);
+        assert_eq!(total.num_data_versions(), 6);
+
+        // Writes are not parsed with `FAST_COUNTS_ONLY`.
+        let mut props = collect_versions(&[("a", 2)]);
+        props.puts_valid = false;
+        assert_eq!(props.num_data_versions(), 2);
+        assert!(!props.is_field_valid(PropKey::NumNonDataVersions));
+    }
+
+    #[test]
+    fn test_entry_hook() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let hook_count = count.clone();
+        let mut collector = UserPropertiesCollector::default();
+        collector.set_entry_hook(Box::new(move |key, _, _| {
+            assert!(keys::validate_data_key(key));
+            hook_count.fetch_add(1, Ordering::SeqCst);
+        }));
+        for ts in 0..10 {
+            let k = keys::data_key(Key::from_raw(b"k").append_ts(ts).encoded());
+            let v = Write::new(WriteType::Put, ts, None).to_bytes();
+            collector.add(&k, &v, DBEntryType::Put, 0, 0);
+        }
+        let props = UserProperties::decode(&collector.finish()).unwrap();
+        assert_eq!(count.load(Ordering::SeqCst) as u64, props.entry_count);
+        assert_eq!(props.mvcc.num_versions, 10);
+
+        // Invalid entries are not passed to the hook.
+        collector.reset();
+        collector.add(b"", b"", DBEntryType::Put, 0, 0);
+        collector.add(b"k", b"v", DBEntryType::Put, 0, 0);
+        assert_eq!(count.load(Ordering::SeqCst), 10);
+    }
+
+    #[test]
+    fn test_num_pure_tombstone_rows() {
+        let cases = [// Pure deletes, rollbacks and locks don't matter.
+                     ("a", 3, WriteType::Delete),
+                     ("a", 2, WriteType::Rollback),
+                     ("a", 1, WriteType::Delete),
+                     // A put shadowed by a delete.
+                     ("b", 2, WriteType::Delete),
+                     ("b", 1, WriteType::Put),
+                     // No deletes at all.
+                     ("c", 2, WriteType::Lock),
+                     ("c", 1, WriteType::Rollback),
+                     // The last row is counted too.
+                     ("d", 1, WriteType::Delete)];
+        let mut collector = UserPropertiesCollector::default();
+        for &(key, ts, write_type) in &cases {
+            let k = keys::data_key(Key::from_raw(key.as_bytes()).append_ts(ts).encoded());
+            let v = Write::new(write_type, ts, None).to_bytes();
+            collector.add(&k, &v, DBEntryType::Put, 0, 0);
+        }
+        let props = MvccProperties::decode(&collector.finish()).unwrap();
+        assert_eq!(props.num_pure_tombstone_rows, 2);
+        assert_eq!(props.num_fully_deleted_rows, 3);
+
+        let mut total = props.clone();
+        total.add(&props);
+        assert_eq!(total.num_pure_tombstone_rows, 4);
+    }
+
+    #[test]
+    fn test_raw_properties() {
+        let mut opts = CollectorOptions::default();
+        opts.cf_mode = CfMode::Raw;
+        let mut collector = UserPropertiesCollector::new(opts);
+        // Raw keys have no ts, and raw values may be empty.
+        let entries: [(&[u8], &[u8], DBEntryType); 4] = [(b"a", b"v", DBEntryType::Put),
+                                                         (b"bb", b"", DBEntryType::Put),
+                                                         (b"c", b"", DBEntryType::Delete),
+                                                         (b"dddddddd", b"vvv", DBEntryType::Put)];
+        for &(k, v, entry_type) in &entries {
+            collector.add(&keys::data_key(k), v, entry_type, 0, 0);
+        }
+        let props = UserProperties::decode(&collector.finish()).unwrap();
+        assert_eq!(props.num_errors, 0);
+        assert!(props.mvcc.is_empty());
+        let raw = props.raw.unwrap();
+        assert_eq!(raw.num_keys, 3);
+        assert_eq!(raw.total_bytes, 2 + 1 + 3 + 0 + 9 + 3);
+
+        let mut total = raw.clone();
+        total.add(&raw);
+        assert_eq!(total.num_keys, 6);
+        assert_eq!(total.total_bytes, 36);
+
+        let props = UserProperties::decode(&UserPropertiesCollector::default().finish()).unwrap();
+        assert!(props.raw.is_none());
+    }
+
+    #[test]
+    fn test_suggest_split_keys() {
+        let mut props = MvccProperties::new();
+        assert!(props.suggest_split_keys(4).is_empty());
+
+        let cases: Vec<(&[u8], &[u8], u64, usize, usize)> =
+            vec![(b"za", b"zz", 100, 4, 3),
+                 (b"za", b"zz", 100, 1, 0),
+                 (b"za", b"zz", 100, 0, 0),
+                 // No more parts than rows.
+                 (b"za", b"zz", 2, 4, 1),
+                 (b"za", b"za", 100, 4, 0),
+                 // The smallest key is a prefix of the largest one.
+                 (b"zk", b"zk\x01", 100, 4, 3),
+                 (b"zk", b"zk\x00", 100, 4, 0),
+                 // Keys longer than the interpolated window.
+                 (b"zkey_0000000001", b"zkey_9999999999", 100, 10, 9),
+                 // Too close to be split into 4 parts.
+                 (b"z\x00",
+                  b"z\x00\x00\x00\x00\x00\x00\x00\x00\x02",
+                  100,
+                  4,
+                  1),
+                 // No more parts than distinct keys between the boundary keys.
+                 (b"z\x00",
+                  b"z\x00\x00\x00\x00\x00\x00\x00\x00\x10",
+                  u64::MAX,
+                  usize::MAX,
+                  15),
+                 (b"za", b"zz", 3, usize::MAX, 2),
+                 (b"za", b"zz", 3, usize::MAX - 1, 2)];
+        for &(smallest, largest, num_rows, parts, expected) in &cases {
+            props.num_rows = num_rows;
+            props.smallest_key = smallest.to_vec();
+            props.largest_key = largest.to_vec();
+            let keys = props.suggest_split_keys(parts);
+            assert_eq!(keys.len(), expected, "{:?} {:?} {}", smallest, largest, parts);
+            let mut last = smallest;
+            for key in &keys {
+                assert!(last < key.as_slice(), "{:?} {:?}", last, key);
+                last = key;
+            }
+            if !keys.is_empty() {
+                assert!(last < largest);
+            }
+        }
+    }
+
+    #[test]
+    fn test_max_value_size() {
+        let mut collector = UserPropertiesCollector::default();
+        for ts in 0..10 {
+            let k = keys::data_key(Key::from_raw(b"k").append_ts(ts).encoded());
+            let v = Write::new(WriteType::Put, ts, Some(vec![0; ts as usize * 5])).to_bytes();
+            collector.add(&k, &v, DBEntryType::Put, 0, 0);
+        }
+        let largest = Write::new(WriteType::Put, 9, Some(vec![0; 45])).to_bytes();
+        let props = MvccProperties::decode(&collector.finish()).unwrap();
+        assert_eq!(props.max_value_size, largest.len() as u64);
+
+        let mut total = MvccProperties::new();
+        total.add(&props);
+        let mut small = MvccProperties::new();
+        small.max_value_size = 1;
+        total.add(&small);
+        assert_eq!(total.max_value_size, largest.len() as u64);
+        // Properties of other column families have no versions.
+        let mut default_cf = MvccProperties::new();
+        default_cf.max_value_size = 1000;
+        total.add(&default_cf);
+        assert_eq!(total.max_value_size, 1000);
+    }
+
+    #[test]
+    fn test_has_deletes() {
+        let collect = |flags, writes: &[WriteType]| {
+            let mut opts = CollectorOptions::from_flags(flags);
+            opts.sample_rate = 2;
+            let mut collector = UserPropertiesCollector::new(opts);
+            for (i, &write_type) in writes.iter().enumerate() {
+                let k = Key::from_raw(b"k").append_ts(100 - i as u64);
+                let k = keys::data_key(k.encoded());
+                let v = Write::new(write_type, 1, None).to_bytes();
+                collector.add(&k, &v, DBEntryType::Put, 0, 0);
+            }
+            MvccProperties::decode(&collector.finish()).unwrap()
+        };
+
+        let puts = [WriteType::Put, WriteType::Put, WriteType::Lock];
+        // The delete isn't sampled, so isn't counted in `num_deletes`.
+        let mixed = [WriteType::Put, WriteType::Delete, WriteType::Put];
+        for &flags in &[MVCC_PROPERTIES, DELETE_RATIO, MVCC_PROPERTIES | FAST_COUNTS_ONLY] {
+            let props = collect(flags, &puts);
+            assert!(!props.has_deletes());
+            let props = collect(flags, &mixed);
+            assert!(props.deletes_seen);
+            assert!(props.has_deletes());
+            assert_eq!(props.num_deletes, 0);
+
+            let mut total = collect(flags, &puts);
+            total.add(&props);
+            assert!(total.has_deletes());
+        }
+
+        // Properties written before `tikv.has_deletes`.
+        let mut props = MvccProperties::with(1, 1, 1, 0, 1, 1);
+        props.num_deletes = 1;
+        let mut encoded = props.encode_with_version(super::PROP_VERSION_V2);
+        encoded.remove(PropKey::HasDeletes.as_bytes());
+        assert!(MvccProperties::decode(&encoded).unwrap().has_deletes());
+    }
+
+    #[test]
+    fn test_validate() {
+        collect_versions(&[("a", 2), ("b", 1)]).validate().unwrap();
+        MvccProperties::new().validate().unwrap();
+        MvccProperties::with(5, 5, 1, 1, 1, 1).validate().unwrap();
+
+        let cases = [(MvccProperties::with(1, 2, 1, 3, 2, 1), "num_puts 3 > num_versions 2"),
+                     (MvccProperties::with(1, 2, 3, 2, 2, 1), "num_rows 3 > num_versions 2"),
+                     (MvccProperties::with(1, 2, 1, 2, 2, 3),
+                      "max_row_versions 3 > num_versions 2"),
+                     (MvccProperties::with(3, 2, 1, 2, 2, 1), "min_ts 3 > max_ts 2")];
+        for &(ref props, expected) in &cases {
+            assert_eq!(props.validate(), Err(expected.to_owned()));
+        }
+    }
+
+    #[test]
+    fn test_mvcc_properties_with() {
+        let props = MvccProperties::with(1, 9, 3, 4, 5, 2);
+        let decoded = MvccProperties::decode(&props.encode()).unwrap();
+        assert_eq!(decoded.min_ts, 1);
+        assert_eq!(decoded.max_ts, 9);
+        assert_eq!(decoded.num_rows, 3);
+        assert_eq!(decoded.num_puts, 4);
+        assert_eq!(decoded.num_versions, 5);
+        assert_eq!(decoded.max_row_versions, 2);
+        // Other fields are left as in `new`.
+        assert!(decoded.puts_valid);
+        assert_eq!(decoded.num_deletes, 0);
+        assert_eq!(decoded.min_row_latest_ts, u64::MAX);
+    }
+
+    #[test]
+    fn test_rows_with_invalid_entries() {
+        let key = |k: &[u8], ts| keys::data_key(Key::from_raw(k).append_ts(ts).encoded());
+        let put = |ts| Write::new(WriteType::Put, ts, None).to_bytes();
+        let mut collector = UserPropertiesCollector::default();
+        collector.add(&key(b"a", 3), &put(3), DBEntryType::Put, 0, 0);
+        // None of these interrupt the versions of row a.
+        collector.add(b"", &put(1), DBEntryType::Put, 0, 0);
+        collector.add(b"a", &put(1), DBEntryType::Put, 0, 0);
+        collector.add(b"za", &put(1), DBEntryType::Put, 0, 0);
+        collector.add(&key(b"b", 1), b"", DBEntryType::Put, 0, 0);
+        collector.add(&key(b"b", 1), &put(1), DBEntryType::Delete, 0, 0);
+        collector.add(&key(b"a", 2), &put(2), DBEntryType::Put, 0, 0);
+        collector.add(&key(b"a", 1), b"bad", DBEntryType::Put, 0, 0);
+        collector.add(&key(b"b", 1), &put(1), DBEntryType::Put, 0, 0);
+
+        let props = UserProperties::decode(&collector.finish()).unwrap();
+        assert_eq!(props.num_non_data_keys, 1);
+        assert_eq!(props.num_errors, 4);
+        let mvcc = props.mvcc;
+        assert_eq!(mvcc.num_rows, 2);
+        assert_eq!(mvcc.num_versions, 4);
+        assert_eq!(mvcc.first_row_versions, 3);
+        assert_eq!(mvcc.last_row_versions, 1);
+        assert_eq!(mvcc.max_row_versions, 3);
+        assert_eq!(mvcc.largest_key, keys::data_key(Key::from_raw(b"b").encoded()));
+    }
+
+    #[test]
+    fn test_ts_range() {
+        assert_eq!(MvccProperties::new().ts_range(), None);
+        assert_eq!(MvccProperties::with(1, 10, 1, 1, 1, 1).ts_range(), Some(1..11));
+        assert_eq!(MvccProperties::with(5, 5, 1, 1, 1, 1).ts_range(), Some(5..6));
+        assert_eq!(MvccProperties::with(0, 0, 1, 1, 1, 1).ts_range(), Some(0..1));
+        // The end saturates.
+        let props = MvccProperties::with(3, u64::MAX, 1, 1, 2, 2);
+        assert_eq!(props.ts_range(), Some(3..u64::MAX));
+
+        // The range isn't collected.
+        let mut props = MvccProperties::new();
+        props.num_versions = 3;
+        assert_eq!(props.ts_range(), None);
+        let mut props = MvccProperties::with(1, 10, 1, 1, 1, 1);
+        props.num_versions = 0;
+        assert_eq!(props.ts_range(), None);
+    }
+
+    #[test]
+    fn test_is_cold() {
+        let props = MvccProperties::with(1, 10, 1, 1, 1, 1);
+        assert!(props.is_cold(100, 89));
+        // Exactly at the threshold.
+        assert!(!props.is_cold(100, 90));
+        assert!(!props.is_cold(10, 0));
+        // The ts is from the future.
+        assert!(!props.is_cold(5, 0));
+        assert!(!props.is_cold(0, 0));
+        // Empty properties have nothing recent.
+        assert!(MvccProperties::new().is_cold(1, 0));
+    }
+
+    #[test]
+    fn test_decode_field() {
+        let mut props = MvccProperties::with(1, 9, 3, 4, 5, 2);
+        props.num_deletes = 1;
+        let versions = [super::PROP_VERSION_V1,
+                        super::PROP_VERSION_V2,
+                        super::CURRENT_PROP_VERSION];
+        for &version in &versions {
+            let mut encoded = props.encode_with_version(version);
+            let decoded = MvccProperties::decode(&encoded).unwrap();
+            for &k in super::PROP_KEYS {
+                assert_eq!(MvccProperties::decode_field(&encoded, k).unwrap(),
+                           decoded.get(k),
+                           "{:?}",
+                           k);
+            }
+
+            encoded.remove(PropKey::NumDeletes.as_bytes());
+            encoded.remove(PropKey::NumRows.as_bytes());
+            assert_eq!(MvccProperties::decode_field(&encoded, PropKey::NumDeletes).unwrap(), 0);
+            assert!(MvccProperties::decode_field(&encoded, PropKey::NumRows).is_err());
+            assert_eq!(MvccProperties::decode_field(&encoded, PropKey::NumPuts).unwrap(), 4);
+        }
+    }
+
+    #[test]
+    fn test_entry_type_counts() {
+        let key = |k: &[u8], ts| keys::data_key(Key::from_raw(k).append_ts(ts).encoded());
+        let put = |ts| Write::new(WriteType::Put, ts, None).to_bytes();
+        let mut collector = UserPropertiesCollector::default();
+        collector.add(&key(b"a", 3), &put(3), DBEntryType::Put, 0, 0);
+        collector.add(&key(b"a", 2), &put(2), DBEntryType::Delete, 0, 0);
+        collector.add(&key(b"a", 1), &put(1), DBEntryType::Merge, 0, 0);
+        collector.add(&key(b"b", 2), &put(2), DBEntryType::SingleDelete, 0, 0);
+        collector.add(&key(b"b", 1), &put(1), DBEntryType::Other, 0, 0);
+        // Invalid entries are counted too.
+        collector.add(b"", &put(1), DBEntryType::Put, 0, 0);
+        collector.add(b"b", &put(1), DBEntryType::Delete, 0, 0);
+        collector.add(&key(b"c", 1), b"bad", DBEntryType::Put, 0, 0);
+
+        let props = UserProperties::decode(&collector.finish()).unwrap();
+        assert_eq!(props.mvcc.entry_type_puts, 3);
+        assert_eq!(props.mvcc.entry_type_deletes, 2);
+        assert_eq!(props.mvcc.entry_type_other, 3);
+        assert_eq!(props.entry_count, 8);
+
+        // Entry types are aggregated even without versions.
+        let mut total = props.mvcc.clone();
+        let mut lock = MvccProperties::new();
+        lock.entry_type_deletes = 1;
+        total.add(&lock);
+        assert_eq!(total.entry_type_puts, 3);
+        assert_eq!(total.entry_type_deletes, 3);
+        assert_eq!(total.entry_type_other, 3);
+    }
+
+    #[test]
+    fn test_add_with_different_flags() {
+        let collect = |flags| {
+            let mut collector = UserPropertiesCollector::new(CollectorOptions::from_flags(flags));
+            for &(k, ts) in &[("a", 2), ("a", 1), ("b", 1)] {
+                let k = keys::data_key(Key::from_raw(k.as_bytes()).append_ts(ts).encoded());
+                let v = Write::new(WriteType::Delete, ts, None).to_bytes();
+                collector.add(&k, &v, DBEntryType::Put, 0, 0);
+            }
+            UserProperties::decode(&collector.finish()).unwrap().mvcc
+        };
+
+        let full = collect(MVCC_PROPERTIES | KEY_CARDINALITY);
+        for &k in super::PROP_KEYS {
+            assert!(full.is_field_valid(k), "{:?}", k);
+        }
+        let mut total = MvccProperties::new();
+        total.add(&full);
+        total.add(&collect(MVCC_PROPERTIES));
+        assert_eq!(total.collected_flags, Some(MVCC_PROPERTIES));
+        assert!(total.is_field_valid(PropKey::NumRows));
+
+        // Rows are not counted with only `DELETE_RATIO`.
+        total.add(&collect(DELETE_RATIO));
+        assert_eq!(total.collected_flags, Some(PropertiesFlags::empty()));
+        assert_eq!((total.num_rows, total.num_versions, total.num_deletes), (4, 9, 9));
+        assert!(!total.is_field_valid(PropKey::NumRows));
+        assert!(!total.is_field_valid(PropKey::MaxTs));
+        assert!(!total.is_field_valid(PropKey::NumFullyDeletedRows));
+        assert!(total.is_field_valid(PropKey::NumVersions));
+        assert!(total.is_field_valid(PropKey::NumDeletes));
+
+        // Writes are not parsed if any operand skips them.
+        let mut total = full.clone();
+        total.add(&collect(MVCC_PROPERTIES | FAST_COUNTS_ONLY));
+        assert_eq!(total.collected_flags, Some(MVCC_PROPERTIES | FAST_COUNTS_ONLY));
+        assert!(!total.is_field_valid(PropKey::NumDeletes));
+        assert!(total.is_field_valid(PropKey::NumRows));
+
+        // Empty operands and operands without flags don't limit the others.
+        let mut total = full.clone();
+        total.add(&collect_versions(&[]));
+        total.add(&MvccProperties::with(1, 1, 1, 1, 1, 1));
+        assert_eq!(total.collected_flags, full.collected_flags);
+        let mut total = MvccProperties::with(1, 1, 1, 1, 1, 1);
+        total.add(&collect(DELETE_RATIO));
+        assert_eq!(total.collected_flags, Some(DELETE_RATIO));
+    }
+
+    #[test]
+    fn test_to_json() {
+        let mut props = collect_versions(&[("a", 2), ("b", 1)]);
+        props.smallest_key = b"a\"\x00".to_vec();
+        props.prefix_bloom = vec![0xab, 0x01];
+        let json = props.to_json();
+        assert!(json.starts_with('{') && json.ends_with('}'), "{}", json);
+        for &k in super::PROP_KEYS {
+            let v = if k == PropKey::PutsValid {
+                "true".to_owned()
+            } else {
+                props.get(k).to_string()
+            };
+            let field = format!("\"{}\":{}", &k.name()["tikv.".len()..], v);
+            assert!(json.contains(&field), "{} not in {}", field, json);
+        }
+        for field in &[r#""versions_histogram":[1,1,0,0,0]"#,
+                       r#""smallest_key":"a\\\"\\000""#,
+                       r#""key_hll":"""#,
+                       r#""prefix_bloom":"ab01""#,
+                       r#""bloom_prefix_len":0"#,
+                       r#""collected_flags":1"#] {
+            assert!(json.contains(field), "{} not in {}", field, json);
+        }
+        assert!(MvccProperties::new().to_json().contains(r#""collected_flags":null"#));
+    }
+
+    #[test]
+    fn test_prop_crc() {
+        let props = MvccProperties::with(1, 9, 3, 4, 5, 2);
+        let mut encoded = props.encode();
+        assert!(encoded.contains_key(super::PROP_CRC.as_bytes()));
+        // Entries of other collectors aren't covered.
+        encoded.insert(b"tikv.other".to_vec(), b"other".to_vec());
+        assert_eq!(MvccProperties::decode(&encoded).unwrap().num_rows, 3);
+
+        let mut corrupted = encoded.clone();
+        corrupted.get_mut(PropKey::NumRows.as_bytes()).unwrap()[0] ^= 1;
+        match MvccProperties::decode(&corrupted) {
+            Err(super::codec::Error::ChecksumMismatch) => {}
+            res => panic!("expect checksum mismatch, got {:?}", res),
+        }
+        corrupted.remove(super::PROP_CRC.as_bytes());
+        assert!(MvccProperties::decode(&corrupted).is_err());
+
+        // Older SSTs have no checksum.
+        let encoded = props.encode_with_version(super::PROP_VERSION_V2);
+        assert!(!encoded.contains_key(super::PROP_CRC.as_bytes()));
+        assert_eq!(MvccProperties::decode(&encoded).unwrap().num_rows, 3);
+    }
+
+    use test::Bencher;
+
+    fn bench_entries() -> Vec<(Vec<u8>, Vec<u8>)> {
+        // Many versions per row, so the row key is rarely copied.
+        let mut entries = Vec::new();
+        for i in 0..100 {
+            let key = format!("row{:03}", i);
+            for ts in (0..10).rev() {
+                let k = Key::from_raw(key.as_bytes()).append_ts(ts);
+                let k = keys::data_key(k.encoded());
+                let v = Write::new(WriteType::Put, ts, None).to_bytes();
+                entries.push((k, v));
+            }
+        }
+        entries
+    }
+
+    fn bench_collect_with_flags(b: &mut Bencher, flags: PropertiesFlags) {
+        let entries = bench_entries();
+        b.iter(|| {
+            let mut collector = UserPropertiesCollector::new(CollectorOptions::from_flags(flags));
+            for &(ref k, ref v) in &entries {
+                collector.add(k, v, DBEntryType::Put, 0, 0);
+            }
+            collector.finish()
+        });
+    }
+
+    #[bench]
+    fn bench_collect_mvcc_properties(b: &mut Bencher) {
+        bench_collect_with_flags(b, MVCC_PROPERTIES);
+    }
+
+    #[bench]
+    fn bench_collect_fast_counts_only(b: &mut Bencher) {
+        bench_collect_with_flags(b, MVCC_PROPERTIES | FAST_COUNTS_ONLY);
+    }
+
+    #[bench]
+    fn bench_collect_without_mvcc(b: &mut Bencher) {
+        bench_collect_with_flags(b, PropertiesFlags::empty());
+    }
+
+    #[bench]
+    fn bench_collect_sketches(b: &mut Bencher) {
+        bench_collect_with_flags(b, MVCC_PROPERTIES | KEY_CARDINALITY | PREFIX_BLOOM);
+    }
+
+    #[bench]
+    fn bench_collect_sketches_reset(b: &mut Bencher) {
+        let entries = bench_entries();
+        let flags = MVCC_PROPERTIES | KEY_CARDINALITY | PREFIX_BLOOM;
+        let mut collector = UserPropertiesCollector::new(CollectorOptions::from_flags(flags));
+        b.iter(|| {
+            collector.reset();
+            for &(ref k, ref v) in &entries {
+                collector.add(k, v, DBEntryType::Put, 0, 0);
+            }
+            collector.finish()
+        });
     }
 }